@@ -130,6 +130,12 @@ async fn main() {
                             DeviceStateUpdate::TouchScreenSwipe((sx, sy), (ex, ey)) => {
                                 println!("Touch Screen swipe from {sx}, {sy} to {ex}, {ey}")
                             }
+
+                            DeviceStateUpdate::Reconnected => {
+                                println!("Device reconnected")
+                            }
+
+                            _ => {}
                         }
                     }
                 }
@@ -105,6 +105,12 @@ fn main() {
                                 DeviceStateUpdate::TouchScreenSwipe((sx, sy), (ex, ey)) => {
                                     println!("Touch Screen swipe from {sx}, {sy} to {ex}, {ey}")
                                 }
+
+                                DeviceStateUpdate::Reconnected => {
+                                    println!("Device reconnected")
+                                }
+
+                                _ => {}
                             }
                         }
                     }
@@ -0,0 +1,486 @@
+//! Async API that doesn't depend on any particular executor.
+//!
+//! Device I/O happens on dedicated worker threads that exchange messages with async code through [async_channel],
+//! so futures from this module can be awaited from tokio, smol, async-std or a plain `block_on`
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate};
+//! use elgato_streamdeck::agnostic::AgnosticStreamDeck;
+//! use elgato_streamdeck::info::Kind;
+//!
+//! # async fn example() {
+//! let hidapi = new_hidapi().unwrap();
+//! let device = AgnosticStreamDeck::connect(&hidapi, Kind::Mk2, "AL12K2C02059").unwrap();
+//!
+//! device.set_brightness(50).await.unwrap();
+//!
+//! let mut updates = device.updates();
+//!
+//! while let Some(update) = updates.next().await {
+//!     if let DeviceStateUpdate::ButtonDown(key) = update {
+//!         device.clear_button_image(key).await.unwrap();
+//!         device.flush().await.unwrap();
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_channel::{Receiver, Sender};
+use futures_core::Stream;
+use hidapi::{HidApi, HidDevice};
+use image::DynamicImage;
+
+use crate::chords::ChordDetector;
+use crate::encoders::EncoderTracker;
+use crate::gestures::GestureRecognizer;
+use crate::images::{ConversionOptions, ImageRect};
+use crate::info::Kind;
+use crate::transport::Transport;
+use crate::{DeviceStateReader, DeviceStateUpdate, StreamDeck, StreamDeckError, StreamDeckInput, TimedUpdate, READ_SLICE};
+
+type Job<T> = Box<dyn FnOnce(&T) + Send>;
+
+/// Thread that runs jobs with a shared value one at a time, stops once every sender is dropped
+fn spawn_worker<T: Send + Sync + 'static>(value: Arc<T>) -> Sender<Job<T>> {
+    let (sender, receiver) = async_channel::unbounded::<Job<T>>();
+
+    thread::spawn(move || {
+        while let Ok(job) = receiver.recv_blocking() {
+            job(&value);
+        }
+    });
+
+    sender
+}
+
+/// Sends the function to the worker and waits for its result
+async fn run_on<T, R: Send + 'static>(jobs: &Sender<Job<T>>, f: impl FnOnce(&T) -> Result<R, StreamDeckError> + Send + 'static) -> Result<R, StreamDeckError> {
+    let (sender, receiver) = async_channel::bounded(1);
+
+    jobs.send(Box::new(move |value| {
+        let _ = sender.send_blocking(f(value));
+    }))
+    .await
+    .map_err(|_| StreamDeckError::WorkerStopped)?;
+
+    receiver.recv().await.map_err(|_| StreamDeckError::WorkerStopped)?
+}
+
+/// Stream Deck interface that can be used with any async executor, every call is sent to a dedicated I/O thread.
+/// Reads have a thread of their own, so waiting for input doesn't hold up writes
+pub struct AgnosticStreamDeck<T: Transport + Send + 'static = HidDevice> {
+    kind: Kind,
+    device: Arc<StreamDeck<T>>,
+    jobs: Sender<Job<StreamDeck<T>>>,
+    reads: Sender<Job<StreamDeck<T>>>,
+}
+
+impl<T: Transport + Send + 'static> Clone for AgnosticStreamDeck<T> {
+    fn clone(&self) -> Self {
+        AgnosticStreamDeck {
+            kind: self.kind,
+            device: self.device.clone(),
+            jobs: self.jobs.clone(),
+            reads: self.reads.clone(),
+        }
+    }
+}
+
+/// Static functions of the struct
+impl AgnosticStreamDeck {
+    /// Attempts to connect to the device. Blocks for as long as opening the device takes, which is usually very short
+    pub fn connect(hidapi: &HidApi, kind: Kind, serial: &str) -> Result<AgnosticStreamDeck, StreamDeckError> {
+        Ok(AgnosticStreamDeck::new(StreamDeck::connect(hidapi, kind, serial)?))
+    }
+}
+
+/// Static functions of the struct
+impl<T: Transport + Send + 'static> AgnosticStreamDeck<T> {
+    /// Wraps already connected device, starting its I/O threads
+    pub fn new(device: StreamDeck<T>) -> AgnosticStreamDeck<T> {
+        let device = Arc::new(device);
+
+        AgnosticStreamDeck {
+            kind: device.kind(),
+            jobs: spawn_worker(device.clone()),
+            reads: spawn_worker(device.clone()),
+            device,
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport + Send + 'static> AgnosticStreamDeck<T> {
+    /// Returns kind of the Stream Deck
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Returns the underlying device, which can also be used from blocking code
+    pub fn device(&self) -> &Arc<StreamDeck<T>> {
+        &self.device
+    }
+
+    async fn run<R: Send + 'static>(&self, f: impl FnOnce(&StreamDeck<T>) -> Result<R, StreamDeckError> + Send + 'static) -> Result<R, StreamDeckError> {
+        run_on(&self.jobs, f).await
+    }
+
+    /// Returns manufacturer string of the device
+    pub async fn manufacturer(&self) -> Result<String, StreamDeckError> {
+        self.run(|device| device.manufacturer()).await
+    }
+
+    /// Returns product string of the device
+    pub async fn product(&self) -> Result<String, StreamDeckError> {
+        self.run(|device| device.product()).await
+    }
+
+    /// Returns serial number of the device
+    pub async fn serial_number(&self) -> Result<String, StreamDeckError> {
+        self.run(|device| device.serial_number()).await
+    }
+
+    /// Returns firmware version of the StreamDeck
+    pub async fn firmware_version(&self) -> Result<String, StreamDeckError> {
+        self.run(|device| device.firmware_version()).await
+    }
+
+    /// Reads button states, waits for input up to timeout if it's specified, returns immediately otherwise.
+    /// Returns [NoData](StreamDeckInput::NoData) if there was nothing to read
+    pub async fn read_input(&self, timeout: Option<Duration>) -> Result<StreamDeckInput, StreamDeckError> {
+        Ok(self.read_input_timed(timeout).await?.0)
+    }
+
+    /// Reads button states like [read_input](AgnosticStreamDeck::read_input), together with the time the report was read at
+    pub async fn read_input_timed(&self, timeout: Option<Duration>) -> Result<(StreamDeckInput, Instant), StreamDeckError> {
+        run_on(&self.reads, move |device| device.read_input_timed(timeout)).await
+    }
+
+    /// Resets the device
+    pub async fn reset(&self) -> Result<(), StreamDeckError> {
+        self.run(|device| device.reset()).await
+    }
+
+    /// Sets brightness of the device, value range is 0 - 100
+    pub async fn set_brightness(&self, percent: u8) -> Result<(), StreamDeckError> {
+        self.run(move |device| device.set_brightness(percent)).await
+    }
+
+    /// Writes image data to Stream Deck device, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub async fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), StreamDeckError> {
+        let image_data = image_data.to_vec();
+        self.run(move |device| device.write_image(key, &image_data)).await
+    }
+
+    /// Writes image data to Stream Deck device's lcd strip/screen as region.
+    /// Only Stream Deck Plus supports writing LCD regions, for Stream Deck Neo use write_lcd_fill
+    pub async fn write_lcd(&self, x: u16, y: u16, rect: &ImageRect) -> Result<(), StreamDeckError> {
+        let rect = rect.clone();
+        self.run(move |device| device.write_lcd(x, y, &rect)).await
+    }
+
+    /// Writes image data to Stream Deck device's lcd strip/screen as full fill
+    ///
+    /// You can convert your images into proper image_data like this:
+    /// ```no_run
+    /// # async fn example(device: elgato_streamdeck::agnostic::AgnosticStreamDeck, image: image::DynamicImage) {
+    /// use elgato_streamdeck::images::convert_image_with_format;
+    /// let image_data = convert_image_with_format(device.kind().lcd_image_format().unwrap(), image).unwrap();
+    /// device.write_lcd_fill(&image_data).await;
+    /// # }
+    /// ```
+    pub async fn write_lcd_fill(&self, image_data: &[u8]) -> Result<(), StreamDeckError> {
+        let image_data = image_data.to_vec();
+        self.run(move |device| device.write_lcd_fill(&image_data)).await
+    }
+
+    /// Sets button's image to blank, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub async fn clear_button_image(&self, key: u8) -> Result<(), StreamDeckError> {
+        self.run(move |device| device.clear_button_image(key)).await
+    }
+
+    /// Sets blank images to every button, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub async fn clear_all_button_images(&self) -> Result<(), StreamDeckError> {
+        self.run(|device| device.clear_all_button_images()).await
+    }
+
+    /// Sets specified button's image, changes must be flushed with `.flush()` before
+    /// they will appear on the device! Image is converted on the I/O thread
+    pub async fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), StreamDeckError> {
+        self.run(move |device| device.set_button_image(key, image)).await
+    }
+
+    /// Sets images of several buttons at once, images are converted in parallel with the I/O thread waiting for them.
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub async fn set_button_images(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>) -> Result<(), StreamDeckError> {
+        let images = images.into_iter().collect::<Vec<_>>();
+        self.run(move |device| device.set_button_images(images)).await
+    }
+
+    /// Sets specified button's image converted with provided options, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub async fn set_button_image_with_options(&self, key: u8, image: DynamicImage, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        let options = options.clone();
+        self.run(move |device| device.set_button_image_with_options(key, image, &options)).await
+    }
+
+    /// Sets images of several buttons at once converted in parallel with provided options.
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub async fn set_button_images_with_options(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        let images = images.into_iter().collect::<Vec<_>>();
+        let options = options.clone();
+        self.run(move |device| device.set_button_images_with_options(images, &options)).await
+    }
+
+    /// Sets specified touch point's led strip color
+    pub async fn set_touchpoint_color(&self, point: u8, red: u8, green: u8, blue: u8) -> Result<(), StreamDeckError> {
+        self.run(move |device| device.set_touchpoint_color(point, red, green, blue)).await
+    }
+
+    /// Flushes the button's image to the device, sending at most one image per key and skipping keys that already show the image.
+    /// Returns keys that were uploaded
+    pub async fn flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.run(|device| device.flush()).await
+    }
+
+    /// Flushes the button's image to the device, uploading every image even if the key already shows it
+    pub async fn force_flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.run(|device| device.force_flush()).await
+    }
+
+    /// Starts reading the device on a dedicated thread and returns stream of updates,
+    /// shortcut for [updates](AgnosticDeviceStateReader::updates) of a new reader
+    pub fn updates(&self) -> AgnosticUpdateStream {
+        self.get_reader().updates()
+    }
+
+    /// Returns button state reader for this device, reads happen on a thread of their own so they don't hold up writes
+    pub fn get_reader(&self) -> Arc<AgnosticDeviceStateReader<T>> {
+        let reader = self.device.get_reader();
+
+        Arc::new(AgnosticDeviceStateReader {
+            jobs: spawn_worker(reader.clone()),
+            reader,
+        })
+    }
+}
+
+/// Button reader that keeps state of the Stream Deck and returns events instead of full states, can be used with any async executor
+pub struct AgnosticDeviceStateReader<T: Transport + Send + 'static = HidDevice> {
+    reader: Arc<DeviceStateReader<T>>,
+    jobs: Sender<Job<DeviceStateReader<T>>>,
+}
+
+impl<T: Transport + Send + 'static> AgnosticDeviceStateReader<T> {
+    /// Reads states and returns updates, waits for input up to timeout if it's specified, returns immediately otherwise
+    pub async fn read(&self, timeout: Option<Duration>) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
+        Ok(self.read_timed(timeout).await?.into_iter().map(|timed| timed.update).collect())
+    }
+
+    /// Reads states and returns updates together with time they happened at and how long things were held for
+    pub async fn read_timed(&self, timeout: Option<Duration>) -> Result<Vec<TimedUpdate>, StreamDeckError> {
+        run_on(&self.jobs, move |reader| reader.read_timed(timeout)).await
+    }
+
+    /// Starts reading the device on a dedicated thread and returns stream of updates.
+    /// Gestures, chords and encoder tracking of the reader apply to the stream,
+    /// reader shouldn't be read in any other way while the stream exists. Reading stops once the stream is dropped
+    pub fn updates(&self) -> AgnosticUpdateStream {
+        let (sender, receiver) = async_channel::unbounded();
+        let error = Arc::new(Mutex::new(None));
+
+        let reader = self.reader.clone();
+        let thread_error = error.clone();
+
+        thread::spawn(move || {
+            while !sender.is_closed() {
+                let updates = match reader.read_timed(Some(READ_SLICE)) {
+                    Ok(updates) => updates,
+                    Err(err) => {
+                        *thread_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(err);
+                        return;
+                    }
+                };
+
+                for timed in updates {
+                    if sender.send_blocking(timed.update).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        AgnosticUpdateStream {
+            receiver: Box::pin(receiver),
+            error,
+        }
+    }
+
+    /// Enables gesture recognition with provided recognizer, or disables it if None
+    pub fn set_gestures(&self, gestures: Option<GestureRecognizer>) -> Result<(), StreamDeckError> {
+        self.reader.set_gestures(gestures)
+    }
+
+    /// Enables chord detection with provided detector, or disables it if None
+    pub fn set_chords(&self, chords: Option<ChordDetector>) -> Result<(), StreamDeckError> {
+        self.reader.set_chords(chords)
+    }
+
+    /// Enables encoder value tracking with provided tracker, or disables it if None
+    pub fn set_encoders(&self, encoders: Option<EncoderTracker>) -> Result<(), StreamDeckError> {
+        self.reader.set_encoders(encoders)
+    }
+
+    /// Enables or disables updates about encoder segments of the touch screen being tapped, long pressed and swiped
+    pub fn set_segments(&self, enabled: bool) -> Result<(), StreamDeckError> {
+        self.reader.set_segments(enabled)
+    }
+}
+
+/// Stream of updates that are read by a dedicated thread, created with [updates](AgnosticDeviceStateReader::updates)
+pub struct AgnosticUpdateStream {
+    // Receiver of async-channel isn't Unpin
+    receiver: Pin<Box<Receiver<DeviceStateUpdate>>>,
+    error: Arc<Mutex<Option<StreamDeckError>>>,
+}
+
+/// Instance methods of the struct
+impl AgnosticUpdateStream {
+    /// Waits for the next update, returns None if reading the device failed
+    pub async fn next(&mut self) -> Option<DeviceStateUpdate> {
+        self.receiver.recv().await.ok()
+    }
+
+    /// Returns error that stopped the stream, if there was one
+    pub fn take_error(&self) -> Option<StreamDeckError> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl Stream for AgnosticUpdateStream {
+    type Item = DeviceStateUpdate;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::mock::{mock_deck, MockDevice};
+
+    /// Minimal executor, to show that nothing here needs a particular runtime
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct ThreadWaker(Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn agnostic_deck() -> (MockDevice, AgnosticStreamDeck<MockDevice>) {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        (mock, AgnosticStreamDeck::new(device))
+    }
+
+    #[test]
+    fn jobs_return_results_and_errors() {
+        let jobs = spawn_worker(Arc::new(21));
+
+        assert_eq!(block_on(run_on(&jobs, |value| Ok(value * 2))).unwrap(), 42);
+        assert!(matches!(block_on(run_on(&jobs, |_| Err::<(), _>(StreamDeckError::BadData))), Err(StreamDeckError::BadData)));
+    }
+
+    #[test]
+    fn stopped_worker_is_reported() {
+        let jobs = spawn_worker(Arc::new(()));
+
+        let result = block_on(run_on(&jobs, |_| -> Result<(), StreamDeckError> { panic!("job failed") }));
+        assert!(matches!(result, Err(StreamDeckError::WorkerStopped)));
+        assert!(matches!(block_on(run_on(&jobs, |_| Ok(()))), Err(StreamDeckError::WorkerStopped)));
+    }
+
+    #[test]
+    fn calls_reach_the_device() {
+        let (mock, device) = agnostic_deck();
+
+        block_on(device.set_brightness(50)).unwrap();
+        assert_eq!(mock.feature_reports()[0][..3], [0x03, 0x08, 50]);
+
+        block_on(device.write_image(2, &[1; 100])).unwrap();
+        assert_eq!(block_on(device.flush()).unwrap(), [2]);
+        assert_eq!(mock.key_image(2), Some(vec![1; 100]));
+
+        mock.queue_button_states(&[true]);
+        assert!(matches!(block_on(device.read_input(Some(Duration::from_secs(1)))).unwrap(), StreamDeckInput::ButtonStateChange(_)));
+    }
+
+    #[test]
+    fn device_errors_are_returned() {
+        let (mock, device) = agnostic_deck();
+
+        mock.set_disconnected(true);
+        assert!(matches!(block_on(device.set_brightness(50)), Err(StreamDeckError::HidError(_))));
+        assert!(matches!(block_on(device.get_reader().read(None)), Err(StreamDeckError::HidError(_))));
+    }
+
+    #[test]
+    fn update_stream_ends_with_read_error() {
+        let (mock, device) = agnostic_deck();
+        let mut updates = device.updates();
+
+        mock.queue_button_states(&[false, true]);
+        assert!(matches!(block_on(updates.next()), Some(DeviceStateUpdate::ButtonDown(1))));
+
+        mock.set_disconnected(true);
+        assert!(block_on(updates.next()).is_none());
+        assert!(matches!(updates.take_error(), Some(StreamDeckError::HidError(_))));
+    }
+
+    #[test]
+    fn reading_stops_once_update_stream_is_dropped() {
+        let (_mock, device) = agnostic_deck();
+        let references = Arc::strong_count(device.device());
+
+        let updates = device.updates();
+        assert!(Arc::strong_count(device.device()) > references);
+        drop(updates);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Arc::strong_count(device.device()) > references && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(Arc::strong_count(device.device()), references);
+    }
+}
@@ -1,259 +1,271 @@
-//! Code from this module is using [block_in_place](tokio::task::block_in_place),
-//! and so they cannot be used in [current_thread](tokio::runtime::Builder::new_current_thread) runtimes
+//! Async wrappers around the blocking API of the crate.
+//!
+//! Device I/O of [AsyncStreamDeck] happens on the dedicated threads of the [executor-neutral core](crate::agnostic),
+//! so everything in this module works on both [current_thread](tokio::runtime::Builder::new_current_thread) and [multi_thread](tokio::runtime::Builder::new_multi_thread) runtimes
 
-use std::iter::zip;
+use std::ops::Deref;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
-
-use hidapi::{HidApi, HidResult};
-use image::DynamicImage;
-use tokio::sync::Mutex;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use hidapi::{HidApi, HidDevice, HidResult};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::mpsc;
 use tokio::task::block_in_place;
-use tokio::time::sleep;
 
-use crate::{DeviceState, DeviceStateUpdate, Kind, list_devices, StreamDeck, StreamDeckError, StreamDeckInput};
-use crate::images::{convert_image_async, ImageRect};
+use crate::{DeviceStateUpdate, Kind, list_devices, StreamDeck, StreamDeckError, StreamDeckInput, TimedUpdate};
+use crate::agnostic::{AgnosticDeviceStateReader, AgnosticStreamDeck, AgnosticUpdateStream};
+use crate::transport::Transport;
+use crate::watcher::{DeviceEvent, DeviceWatcher};
+
+/// Runs blocking code that borrows from the caller. Uses [block_in_place] inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtimes
+/// so other tasks can move to another worker, runs it directly anywhere else since [block_in_place] would panic there
+pub(crate) fn run_blocking<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(f),
+        _ => f(),
+    }
+}
 
-/// Actually refreshes the device list, can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+/// Actually refreshes the device list, can be safely ran inside any runtime
 pub fn refresh_device_list_async(hidapi: &mut HidApi) -> HidResult<()> {
-    block_in_place(move || hidapi.refresh_devices())
+    run_blocking(move || hidapi.refresh_devices())
 }
 
 /// Returns a list of devices as (Kind, Serial Number) that could be found using HidApi,
-/// can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+/// can be safely ran inside any runtime
 ///
-/// **WARNING:** To refresh the list, use [refresh_device_list]
+/// **WARNING:** To refresh the list, use [refresh_device_list](crate::refresh_device_list)
 pub fn list_devices_async(hidapi: &HidApi) -> Vec<(Kind, String)> {
-    block_in_place(move || list_devices(hidapi))
+    run_blocking(move || list_devices(hidapi))
+}
+
+/// Stream Deck interface suitable to be used in async, a tokio flavored wrapper around [AgnosticStreamDeck].
+/// Every call is sent to the device's I/O thread so it doesn't stall the runtime, which can be of any flavor.
+/// Methods that don't depend on tokio are available through [Deref]
+pub struct AsyncStreamDeck<T: Transport + Send + 'static = HidDevice> {
+    inner: AgnosticStreamDeck<T>,
 }
 
-/// Stream Deck interface suitable to be used in async, uses [block_in_place](block_in_place)
-/// so this wrapper cannot be used in [current_thread](tokio::runtime::Builder::new_current_thread) runtimes
-#[derive(Clone)]
-pub struct AsyncStreamDeck {
-    kind: Kind,
-    device: Arc<Mutex<StreamDeck>>,
+impl<T: Transport + Send + 'static> Clone for AsyncStreamDeck<T> {
+    fn clone(&self) -> Self {
+        AsyncStreamDeck { inner: self.inner.clone() }
+    }
 }
 
 /// Static functions of the struct
 impl AsyncStreamDeck {
-    /// Attempts to connect to the device, can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+    /// Attempts to connect to the device, can be safely ran inside any runtime
     pub fn connect(hidapi: &HidApi, kind: Kind, serial: &str) -> Result<AsyncStreamDeck, StreamDeckError> {
-        let device = block_in_place(move || StreamDeck::connect(hidapi, kind, serial))?;
+        let device = run_blocking(move || StreamDeck::connect(hidapi, kind, serial))?;
+        Ok(AsyncStreamDeck::new(device))
+    }
+}
 
-        Ok(AsyncStreamDeck {
-            kind,
-            device: Arc::new(Mutex::new(device)),
-        })
+/// Static functions of the struct
+impl<T: Transport + Send + 'static> AsyncStreamDeck<T> {
+    /// Wraps already connected device, starting its I/O threads
+    pub fn new(device: StreamDeck<T>) -> AsyncStreamDeck<T> {
+        AsyncStreamDeck {
+            inner: AgnosticStreamDeck::new(device),
+        }
     }
 }
 
 /// Instance methods of the struct
-impl AsyncStreamDeck {
-    /// Returns kind of the Stream Deck
-    pub fn kind(&self) -> Kind {
-        self.kind
+impl<T: Transport + Send + 'static> AsyncStreamDeck<T> {
+    /// Reads button states, awaits until there's data.
+    /// Poll rate determines how many times per second waiting for input starts over, input itself is returned as soon as it's read
+    pub async fn read_input(&self, poll_rate: f32) -> Result<StreamDeckInput, StreamDeckError> {
+        Ok(self.read_input_timed(poll_rate).await?.0)
     }
 
-    /// Returns manufacturer string of the device
-    pub async fn manufacturer(&self) -> Result<String, StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.manufacturer())
+    /// Reads button states, awaits until there's data. Returns the input together with the time the report was read at
+    pub async fn read_input_timed(&self, poll_rate: f32) -> Result<(StreamDeckInput, Instant), StreamDeckError> {
+        let poll_interval = Duration::from_secs_f32(1.0 / poll_rate);
+
+        loop {
+            let (data, timestamp) = self.inner.read_input_timed(Some(poll_interval)).await?;
+
+            if !data.is_empty() {
+                return Ok((data, timestamp));
+            }
+        }
     }
 
-    /// Returns product string of the device
-    pub async fn product(&self) -> Result<String, StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.product())
+    /// Returns button state reader for this device
+    pub fn get_reader(&self) -> Arc<AsyncDeviceStateReader<T>> {
+        Arc::new(AsyncDeviceStateReader { inner: self.inner.get_reader() })
     }
+}
+
+impl<T: Transport + Send + 'static> Deref for AsyncStreamDeck<T> {
+    type Target = AgnosticStreamDeck<T>;
 
-    /// Returns serial number of the device
-    pub async fn serial_number(&self) -> Result<String, StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.serial_number())
+    fn deref(&self) -> &AgnosticStreamDeck<T> {
+        &self.inner
     }
+}
+
+/// Button reader that keeps state of the Stream Deck and returns events instead of full states,
+/// a tokio flavored wrapper around [AgnosticDeviceStateReader]. Methods that don't depend on tokio are available through [Deref]
+pub struct AsyncDeviceStateReader<T: Transport + Send + 'static = HidDevice> {
+    inner: Arc<AgnosticDeviceStateReader<T>>,
+}
 
-    /// Returns firmware version of the StreamDeck
-    pub async fn firmware_version(&self) -> Result<String, StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.firmware_version())
+/// Instance methods of the struct
+impl<T: Transport + Send + 'static> AsyncDeviceStateReader<T> {
+    /// Reads states and returns updates, awaits until there are any.
+    /// Poll rate determines how many times per second waiting for input starts over, input itself is returned as soon as it's read
+    pub async fn read(&self, poll_rate: f32) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
+        Ok(self.read_timed(poll_rate).await?.into_iter().map(|timed| timed.update).collect())
     }
 
-    /// Reads button states, awaits until there's data.
-    /// Poll rate determines how often button state gets checked
-    pub async fn read_input(&self, poll_rate: f32) -> Result<StreamDeckInput, StreamDeckError> {
+    /// Reads states and returns updates together with time they happened at and how long things were held for
+    pub async fn read_timed(&self, poll_rate: f32) -> Result<Vec<TimedUpdate>, StreamDeckError> {
+        let poll_interval = Duration::from_secs_f32(1.0 / poll_rate);
+
+        // Reads wake up early on their own if a gesture or held back update becomes due
         loop {
-            let device = self.device.lock().await;
-            let data = block_in_place(move || device.read_input(None))?;
+            let updates = self.inner.read_timed(Some(poll_interval)).await?;
 
-            if !data.is_empty() {
-                return Ok(data);
+            if !updates.is_empty() {
+                return Ok(updates);
             }
-
-            sleep(Duration::from_secs_f32(1.0 / poll_rate)).await;
         }
     }
+}
 
-    /// Resets the device
-    pub async fn reset(&self) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.reset())
-    }
+impl<T: Transport + Send + 'static> Deref for AsyncDeviceStateReader<T> {
+    type Target = AgnosticDeviceStateReader<T>;
 
-    /// Sets brightness of the device, value range is 0 - 100
-    pub async fn set_brightness(&self, percent: u8) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.set_brightness(percent))
+    fn deref(&self) -> &AgnosticDeviceStateReader<T> {
+        &self.inner
     }
+}
 
-    /// Writes image data to Stream Deck device, changes must be flushed with `.flush()` before
-    /// they will appear on the device!
-    pub async fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.write_image(key, image_data))
-    }
+/// Stream of updates that are read by a dedicated thread, created with [updates](AgnosticDeviceStateReader::updates).
+/// Can be used in any runtime
+pub type UpdateStream = AgnosticUpdateStream;
 
-    /// Writes image data to Stream Deck device's lcd strip/screen as region.
-    /// Only Stream Deck Plus supports writing LCD regions, for Stream Deck Neo use write_lcd_fill
-    pub async fn write_lcd(&self, x: u16, y: u16, rect: &ImageRect) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.write_lcd(x, y, rect))
-    }
+/// Async version of [DeviceWatcher], polls for devices on a separate thread and yields events as a [Stream]
+pub struct AsyncDeviceWatcher {
+    receiver: mpsc::UnboundedReceiver<Result<DeviceEvent, StreamDeckError>>,
+}
 
-    /// Writes image data to Stream Deck device's lcd strip/screen as full fill
-    ///
-    /// You can convert your images into proper image_data like this:
-    /// ```
-    /// use elgato_streamdeck::images::{convert_image_with_format_async};
-    /// let image_data = convert_image_with_format_async(device.kind().lcd_image_format(), image).await.unwrap();
-    /// device.write_lcd_fill(&image_data).await;
-    /// ```
-    pub async fn write_lcd_fill(&self, image_data: &[u8]) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.write_lcd_fill(image_data))
-    }
+/// Static functions of the struct
+impl AsyncDeviceWatcher {
+    /// Creates watcher that will poll the HidApi every `poll_rate`, polling stops when the watcher is dropped
+    pub fn new(hidapi: HidApi, poll_rate: Duration) -> AsyncDeviceWatcher {
+        let (sender, receiver) = mpsc::unbounded_channel();
 
-    /// Sets button's image to blank, changes must be flushed with `.flush()` before
-    /// they will appear on the device!
-    pub async fn clear_button_image(&self, key: u8) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.clear_button_image(key))
-    }
+        thread::spawn(move || {
+            let mut watcher = DeviceWatcher::new(hidapi, poll_rate);
 
-    /// Sets blank images to every button, changes must be flushed with `.flush()` before
-    /// they will appear on the device!
-    pub async fn clear_all_button_images(&self) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.clear_all_button_images())
-    }
+            while !sender.is_closed() {
+                let events = watcher.poll();
+                let failed = events.is_err();
 
-    /// Sets specified button's image, changes must be flushed with `.flush()` before
-    /// they will appear on the device!
-    pub async fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), StreamDeckError> {
-        let image = convert_image_async(self.kind, image)?;
+                for event in events.map_or_else(|err| vec![Err(err)], |events| events.into_iter().map(Ok).collect()) {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
 
-        let device = self.device.lock().await;
-        block_in_place(move || device.write_image(key, &image))
-    }
+                if failed {
+                    return;
+                }
 
-    /// Sets specified touch point's led strip color
-    pub async fn set_touchpoint_color(&self, point: u8, red: u8, green: u8, blue: u8) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.set_touchpoint_color(point, red, green, blue))
-    }
+                thread::sleep(poll_rate);
+            }
+        });
 
-    /// Flushes the button's image to the device
-    pub async fn flush(&self) -> Result<(), StreamDeckError> {
-        let device = self.device.lock().await;
-        block_in_place(move || device.flush())
+        AsyncDeviceWatcher { receiver }
     }
+}
 
-    /// Returns button state reader for this device
-    pub fn get_reader(&self) -> Arc<AsyncDeviceStateReader> {
-        Arc::new(AsyncDeviceStateReader {
-            device: self.clone(),
-            states: Mutex::new(DeviceState {
-                buttons: vec![false; self.kind.key_count() as usize + self.kind.touchpoint_count() as usize],
-                encoders: vec![false; self.kind.encoder_count() as usize],
-            }),
-        })
+/// Instance methods of the struct
+impl AsyncDeviceWatcher {
+    /// Waits for the next event, returns None if polling was stopped by an error
+    pub async fn next(&mut self) -> Option<Result<DeviceEvent, StreamDeckError>> {
+        self.receiver.recv().await
     }
 }
 
-/// Button reader that keeps state of the Stream Deck and returns events instead of full states
-pub struct AsyncDeviceStateReader {
-    device: AsyncStreamDeck,
-    states: Mutex<DeviceState>,
+impl Stream for AsyncDeviceWatcher {
+    type Item = Result<DeviceEvent, StreamDeckError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
 }
 
-impl AsyncDeviceStateReader {
-    /// Reads states and returns updates
-    pub async fn read(&self, poll_rate: f32) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
-        let input = self.device.read_input(poll_rate).await?;
-        let mut my_states = self.states.lock().await;
-
-        let mut updates = vec![];
-
-        match input {
-            StreamDeckInput::ButtonStateChange(buttons) => {
-                for (index, (their, mine)) in zip(buttons.iter(), my_states.buttons.iter()).enumerate() {
-                    if *their != *mine {
-                        if index < self.device.kind.key_count() as usize {
-                            if *their {
-                                updates.push(DeviceStateUpdate::ButtonDown(index as u8));
-                            } else {
-                                updates.push(DeviceStateUpdate::ButtonUp(index as u8));
-                            }
-                        } else if *their {
-                            updates.push(DeviceStateUpdate::TouchPointDown(index as u8 - self.device.kind.key_count()));
-                        } else {
-                            updates.push(DeviceStateUpdate::TouchPointUp(index as u8 - self.device.kind.key_count()));
-                        }
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
 
-                my_states.buttons = buttons;
-            }
+    use super::*;
+    use crate::mock::{mock_deck, MockDevice};
 
-            StreamDeckInput::EncoderStateChange(encoders) => {
-                for (index, (their, mine)) in zip(encoders.iter(), my_states.encoders.iter()).enumerate() {
-                    if *their != *mine {
-                        if *their {
-                            updates.push(DeviceStateUpdate::EncoderDown(index as u8));
-                        } else {
-                            updates.push(DeviceStateUpdate::EncoderUp(index as u8));
-                        }
-                    }
-                }
+    fn async_deck() -> (MockDevice, AsyncStreamDeck<MockDevice>) {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        (mock, AsyncStreamDeck::new(device))
+    }
 
-                my_states.encoders = encoders;
-            }
+    #[tokio::test(flavor = "current_thread")]
+    async fn works_on_current_thread_runtime() {
+        assert_eq!(run_blocking(|| 42), 42);
 
-            StreamDeckInput::EncoderTwist(twist) => {
-                for (index, change) in twist.iter().enumerate() {
-                    if *change != 0 {
-                        updates.push(DeviceStateUpdate::EncoderTwist(index as u8, *change));
-                    }
-                }
-            }
+        let (mock, device) = async_deck();
 
-            StreamDeckInput::TouchScreenPress(x, y) => {
-                updates.push(DeviceStateUpdate::TouchScreenPress(x, y));
-            }
+        device.set_brightness(50).await.unwrap();
+        device.write_image(0, &[1; 100]).await.unwrap();
+        assert_eq!(device.flush().await.unwrap(), [0]);
+        assert_eq!(mock.key_image(0), Some(vec![1; 100]));
 
-            StreamDeckInput::TouchScreenLongPress(x, y) => {
-                updates.push(DeviceStateUpdate::TouchScreenLongPress(x, y));
-            }
+        mock.queue_button_states(&[true]);
+        assert!(matches!(device.read_input(100.0).await.unwrap(), StreamDeckInput::ButtonStateChange(_)));
 
-            StreamDeckInput::TouchScreenSwipe(s, e) => {
-                updates.push(DeviceStateUpdate::TouchScreenSwipe(s, e));
-            }
+        let reader = device.get_reader();
+        mock.queue_button_states(&[false, true]);
+        assert!(matches!(reader.read(100.0).await.unwrap()[..], [DeviceStateUpdate::ButtonDown(1)]));
 
-            _ => {}
-        }
+        let mut updates = device.updates();
+        mock.queue_button_states(&[false, false, true]);
+        assert!(matches!(updates.next().await, Some(DeviceStateUpdate::ButtonDown(2))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn works_on_multi_thread_runtime() {
+        assert_eq!(run_blocking(|| 42), 42);
+
+        let (mock, device) = async_deck();
+
+        device.set_brightness(50).await.unwrap();
+        assert_eq!(mock.feature_reports().len(), 1);
+
+        mock.queue_button_states(&[true]);
+        let reader = device.get_reader();
+        assert!(matches!(reader.read(100.0).await.unwrap()[..], [DeviceStateUpdate::ButtonDown(0)]));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn reads_dont_busy_wait_for_input() {
+        let (mock, device) = async_deck();
+
+        let queuer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            mock.queue_button_states(&[true]);
+        });
 
-        drop(my_states);
+        // Single poll per second, input still arrives as soon as it's queued
+        let started = std::time::Instant::now();
+        device.read_input(1.0).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
 
-        Ok(updates)
+        queuer.join().unwrap();
     }
 }
@@ -0,0 +1,548 @@
+//! Recording and replaying of HID traffic between the crate and the device.
+//!
+//! # Capture format
+//! Captures are plain text files, so they can be attached to bug reports and diffed.
+//! First line is the header, it contains format version, vendor ID and product ID of the device in hex:
+//! ```text
+//! elgato-streamdeck capture v1 0fd9:008f
+//! ```
+//! Every following line is a single report, made of time since the recording started in seconds,
+//! direction of the report and bytes of the report in hex, including report ID:
+//! ```text
+//! 0.000512 get 0600414c31324b32433032303539000000000000000000000000000000000000
+//! 0.001200 set 0308320000000000000000000000000000000000000000000000000000000000
+//! 0.105003 write 020700010c0300000...
+//! 1.520871 read 01002000000100000000000000000000000000000000000000000000000000000000
+//! ```
+//! Directions are:
+//! - `get` - feature report that was received from the device
+//! - `set` - feature report that was sent to the device
+//! - `write` - output report that was sent to the device
+//! - `read` - input report that was received from the device, reads that returned no data are not recorded
+//!
+//! Empty lines and lines starting with `#` are ignored
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hidapi::{HidError, HidResult};
+
+use crate::info::Kind;
+use crate::transport::Transport;
+
+const CAPTURE_HEADER: &str = "elgato-streamdeck capture v1";
+
+/// Direction of the recorded report
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum CaptureDirection {
+    /// Feature report received from the device
+    GetFeatureReport,
+    /// Feature report sent to the device
+    SendFeatureReport,
+    /// Output report sent to the device
+    Write,
+    /// Input report received from the device
+    Read,
+}
+
+impl CaptureDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CaptureDirection::GetFeatureReport => "get",
+            CaptureDirection::SendFeatureReport => "set",
+            CaptureDirection::Write => "write",
+            CaptureDirection::Read => "read",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<CaptureDirection> {
+        match value {
+            "get" => Some(CaptureDirection::GetFeatureReport),
+            "set" => Some(CaptureDirection::SendFeatureReport),
+            "write" => Some(CaptureDirection::Write),
+            "read" => Some(CaptureDirection::Read),
+            _ => None,
+        }
+    }
+}
+
+/// Single recorded report
+#[derive(Clone, Debug)]
+pub struct CaptureEvent {
+    /// Time since the recording started
+    pub timestamp: Duration,
+    /// Direction of the report
+    pub direction: CaptureDirection,
+    /// Bytes of the report, including report ID
+    pub data: Vec<u8>,
+}
+
+/// Parsed capture file
+#[derive(Clone, Debug)]
+pub struct Capture {
+    /// Kind of the device that was recorded
+    pub kind: Kind,
+    /// Recorded reports in order they happened
+    pub events: Vec<CaptureEvent>,
+}
+
+impl Capture {
+    /// Reads capture from a file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Capture> {
+        Capture::parse(BufReader::new(File::open(path)?))
+    }
+
+    /// Parses capture in format described in [module documentation](self)
+    pub fn parse(reader: impl BufRead) -> io::Result<Capture> {
+        let mut lines = reader.lines().enumerate().filter(|(_, line)| match line {
+            Ok(line) => !line.trim().is_empty() && !line.starts_with('#'),
+            Err(_) => true,
+        });
+
+        let header = lines.next().map(|(_, line)| line).transpose()?.unwrap_or_default();
+        let kind = header
+            .strip_prefix(CAPTURE_HEADER)
+            .and_then(|ids| ids.trim().split_once(':'))
+            .and_then(|(vid, pid)| Some((u16::from_str_radix(vid, 16).ok()?, u16::from_str_radix(pid, 16).ok()?)))
+            .and_then(|(vid, pid)| Kind::from_vid_pid(vid, pid))
+            .ok_or_else(|| invalid_data(1, "Invalid capture header"))?;
+
+        let mut events = vec![];
+
+        for (index, line) in lines {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+
+            let timestamp = parts
+                .next()
+                .and_then(|t| t.parse::<f64>().ok())
+                .and_then(|t| Duration::try_from_secs_f64(t).ok())
+                .ok_or_else(|| invalid_data(index + 1, "Invalid timestamp"))?;
+            let direction = parts.next().and_then(CaptureDirection::from_str).ok_or_else(|| invalid_data(index + 1, "Invalid direction"))?;
+            let data = decode_hex(parts.next().unwrap_or_default()).ok_or_else(|| invalid_data(index + 1, "Invalid report bytes"))?;
+
+            events.push(CaptureEvent { timestamp, direction, data });
+        }
+
+        Ok(Capture { kind, events })
+    }
+}
+
+/// Transport wrapper that writes every report going through it into a capture.
+/// Writer is flushed after every report, so the capture is complete even when the transport is never unwrapped,
+/// like after it was given to [StreamDeck](crate::StreamDeck::with_transport)
+pub struct RecordingTransport<T: Transport, W: Write> {
+    inner: T,
+    writer: Mutex<W>,
+    started: Instant,
+}
+
+/// Static functions of the struct
+impl<T: Transport> RecordingTransport<T, File> {
+    /// Wraps the transport, recording into a newly created file
+    pub fn create(kind: Kind, inner: T, path: impl AsRef<Path>) -> io::Result<RecordingTransport<T, File>> {
+        RecordingTransport::new(kind, inner, File::create(path)?)
+    }
+}
+
+/// Static functions of the struct
+impl<T: Transport, W: Write> RecordingTransport<T, W> {
+    /// Wraps the transport of a device of provided kind, recording into provided writer
+    pub fn new(kind: Kind, inner: T, mut writer: W) -> io::Result<RecordingTransport<T, W>> {
+        writeln!(writer, "{} {:04x}:{:04x}", CAPTURE_HEADER, kind.vendor_id(), kind.product_id())?;
+        writer.flush()?;
+
+        Ok(RecordingTransport {
+            inner,
+            writer: Mutex::new(writer),
+            started: Instant::now(),
+        })
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport, W: Write> RecordingTransport<T, W> {
+    /// Returns the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the writer, everything that was recorded has already been flushed to it
+    pub fn into_writer(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, direction: CaptureDirection, data: &[u8]) -> HidResult<()> {
+        let line = format!("{:.6} {} {}", self.started.elapsed().as_secs_f64(), direction.as_str(), encode_hex(data));
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(writer, "{}", line).and_then(|_| writer.flush()).map_err(|error| HidError::IoError { error })
+    }
+}
+
+impl<T: Transport, W: Write> Transport for RecordingTransport<T, W> {
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        let length = self.inner.get_feature_report(buf)?;
+        self.record(CaptureDirection::GetFeatureReport, &buf[..length.min(buf.len())])?;
+        Ok(length)
+    }
+
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()> {
+        self.record(CaptureDirection::SendFeatureReport, payload)?;
+        self.inner.send_feature_report(payload)
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        let length = self.inner.read(buf, timeout)?;
+
+        if length > 0 {
+            self.record(CaptureDirection::Read, &buf[..length.min(buf.len())])?;
+        }
+
+        Ok(length)
+    }
+
+    fn write(&self, payload: &[u8]) -> HidResult<usize> {
+        self.record(CaptureDirection::Write, payload)?;
+        self.inner.write(payload)
+    }
+
+    fn manufacturer_string(&self) -> HidResult<Option<String>> {
+        self.inner.manufacturer_string()
+    }
+
+    fn product_string(&self) -> HidResult<Option<String>> {
+        self.inner.product_string()
+    }
+
+    fn take_reconnected(&self) -> bool {
+        self.inner.take_reconnected()
+    }
+}
+
+/// Difference between what was recorded and what was sent during replay
+#[derive(Clone, Debug)]
+pub struct ReplayMismatch {
+    /// Index of the outgoing report, counting only feature reports sent and output reports written
+    pub index: usize,
+    /// Report that was recorded, None if the capture didn't have any more outgoing reports
+    pub expected: Option<CaptureEvent>,
+    /// Direction of the report that was sent during replay
+    pub direction: CaptureDirection,
+    /// Bytes of the report that was sent during replay
+    pub actual: Vec<u8>,
+}
+
+/// Transport that plays a capture back, answering reads from the recording
+/// and comparing outgoing reports against the recorded ones.
+///
+/// Clones share the same state, so one clone can be given to [StreamDeck](crate::StreamDeck)
+/// while the other is used to check the results
+#[derive(Clone)]
+pub struct ReplayTransport {
+    kind: Kind,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+struct ReplayState {
+    feature_reports: VecDeque<CaptureEvent>,
+    input_reports: VecDeque<CaptureEvent>,
+    outgoing: VecDeque<CaptureEvent>,
+    outgoing_index: usize,
+    mismatches: Vec<ReplayMismatch>,
+}
+
+/// Static functions of the struct
+impl ReplayTransport {
+    /// Reads capture from a file and prepares it for replay
+    pub fn open(path: impl AsRef<Path>) -> io::Result<ReplayTransport> {
+        Ok(ReplayTransport::new(Capture::open(path)?))
+    }
+
+    /// Prepares capture for replay
+    pub fn new(capture: Capture) -> ReplayTransport {
+        let mut state = ReplayState {
+            feature_reports: VecDeque::new(),
+            input_reports: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            outgoing_index: 0,
+            mismatches: vec![],
+        };
+
+        for event in capture.events {
+            match event.direction {
+                CaptureDirection::GetFeatureReport => state.feature_reports.push_back(event),
+                CaptureDirection::Read => state.input_reports.push_back(event),
+                _ => state.outgoing.push_back(event),
+            }
+        }
+
+        ReplayTransport {
+            kind: capture.kind,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl ReplayTransport {
+    /// Returns kind of the device that was recorded
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Returns all outgoing reports that didn't match the recording so far
+    pub fn mismatches(&self) -> Vec<ReplayMismatch> {
+        self.state().mismatches.clone()
+    }
+
+    /// Returns recorded outgoing reports that weren't sent during replay yet
+    pub fn remaining(&self) -> Vec<CaptureEvent> {
+        self.state().outgoing.iter().cloned().collect()
+    }
+
+    /// Checks that every outgoing report matched the recording and that none of the recorded reports were left unsent
+    pub fn verify(&self) -> Result<(), Vec<ReplayMismatch>> {
+        let state = self.state();
+
+        let mut mismatches = state.mismatches.clone();
+
+        for (offset, expected) in state.outgoing.iter().enumerate() {
+            mismatches.push(ReplayMismatch {
+                index: state.outgoing_index + offset,
+                expected: Some(expected.clone()),
+                direction: expected.direction,
+                actual: vec![],
+            });
+        }
+
+        if mismatches.is_empty() { Ok(()) } else { Err(mismatches) }
+    }
+
+    fn check_outgoing(&self, direction: CaptureDirection, payload: &[u8]) {
+        let mut state = self.state();
+
+        let expected = state.outgoing.pop_front();
+        let index = state.outgoing_index;
+        state.outgoing_index += 1;
+
+        let matches = match &expected {
+            Some(expected) => expected.direction == direction && expected.data == payload,
+            None => false,
+        };
+
+        if !matches {
+            state.mismatches.push(ReplayMismatch {
+                index,
+                expected,
+                direction,
+                actual: payload.to_vec(),
+            });
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        let mut state = self.state();
+        let report_id = buf.first().copied();
+
+        let position = state.feature_reports.iter().position(|event| event.data.first().copied() == report_id).ok_or_else(|| HidError::HidApiError {
+            message: format!("Capture doesn't have any more feature reports with ID {:?}", report_id),
+        })?;
+        let event = state.feature_reports.remove(position).unwrap();
+
+        let length = event.data.len().min(buf.len());
+        buf[..length].copy_from_slice(&event.data[..length]);
+
+        Ok(length)
+    }
+
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()> {
+        self.check_outgoing(CaptureDirection::SendFeatureReport, payload);
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        let event = self.state().input_reports.pop_front();
+
+        match event {
+            Some(event) => {
+                let length = event.data.len().min(buf.len());
+                buf[..length].copy_from_slice(&event.data[..length]);
+                Ok(length)
+            }
+
+            None => {
+                // Waiting like a real device would, so readers don't spin once the recording runs out
+                if let Some(timeout) = timeout {
+                    thread::sleep(timeout);
+                }
+
+                Ok(0)
+            }
+        }
+    }
+
+    fn write(&self, payload: &[u8]) -> HidResult<usize> {
+        self.check_outgoing(CaptureDirection::Write, payload);
+        Ok(payload.len())
+    }
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+
+    for byte in data {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+
+    hex
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} on line {}", message, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::{Capture, CaptureDirection, RecordingTransport, ReplayTransport};
+    use crate::info::Kind;
+    use crate::mock::MockDevice;
+    use crate::transport::Transport;
+    use crate::{StreamDeck, StreamDeckInput};
+
+    /// Writer that only lets the caller see flushed bytes
+    #[derive(Clone, Default)]
+    struct Flushed {
+        pending: Arc<Mutex<Vec<u8>>>,
+        flushed: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for Flushed {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.lock().unwrap().append(&mut self.pending.lock().unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reports_are_recorded_while_device_owns_transport() {
+        let writer = Flushed::default();
+        let transport = RecordingTransport::new(Kind::Mk2, MockDevice::new(Kind::Mk2), writer.clone()).unwrap();
+        let device = StreamDeck::with_transport(Kind::Mk2, transport);
+
+        device.set_brightness(50).unwrap();
+
+        let capture = Capture::parse(writer.flushed.lock().unwrap().as_slice()).unwrap();
+        assert!(matches!(capture.kind, Kind::Mk2));
+        assert_eq!(capture.events.len(), 1);
+        assert_eq!(capture.events[0].direction, CaptureDirection::SendFeatureReport);
+        assert_eq!(capture.events[0].data[..3], [0x03, 0x08, 50]);
+    }
+
+    /// Records a short session with a Mk2: serial number, brightness, one key image and one key press
+    fn record_session() -> Capture {
+        let writer = Flushed::default();
+        let mock = MockDevice::new(Kind::Mk2);
+        let device = StreamDeck::with_transport(Kind::Mk2, RecordingTransport::new(Kind::Mk2, mock.clone(), writer.clone()).unwrap());
+
+        mock.queue_button_states(&[false, true]);
+        play_session(&device, 50);
+
+        Capture::parse(writer.flushed.lock().unwrap().as_slice()).unwrap()
+    }
+
+    fn play_session<T: Transport>(device: &StreamDeck<T>, brightness: u8) -> StreamDeckInput {
+        assert_eq!(device.serial_number().unwrap(), "MOCK00000000");
+        device.set_brightness(brightness).unwrap();
+        device.write_image(3, &[7; 100]).unwrap();
+        device.flush().unwrap();
+        device.read_input(None).unwrap()
+    }
+
+    #[test]
+    fn recorded_session_replays_without_mismatches() {
+        let capture = record_session();
+        assert!(matches!(capture.kind, Kind::Mk2));
+
+        let replay = ReplayTransport::new(capture);
+        let device = StreamDeck::with_transport(replay.kind(), replay.clone());
+
+        let input = play_session(&device, 50);
+        assert!(matches!(input, StreamDeckInput::ButtonStateChange(states) if states[1]));
+
+        replay.verify().unwrap();
+        assert!(replay.remaining().is_empty());
+    }
+
+    #[test]
+    fn changed_report_is_a_mismatch() {
+        let replay = ReplayTransport::new(record_session());
+        let device = StreamDeck::with_transport(replay.kind(), replay.clone());
+
+        play_session(&device, 60);
+
+        let mismatches = replay.mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 0);
+        assert_eq!(mismatches[0].direction, CaptureDirection::SendFeatureReport);
+        assert_eq!(mismatches[0].actual[..3], [0x03, 0x08, 60]);
+        assert_eq!(mismatches[0].expected.as_ref().unwrap().data[..3], [0x03, 0x08, 50]);
+        assert_eq!(replay.verify().unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn reports_that_werent_sent_are_remaining() {
+        let replay = ReplayTransport::new(record_session());
+        let device = StreamDeck::with_transport(replay.kind(), replay.clone());
+
+        device.set_brightness(50).unwrap();
+
+        let remaining = replay.remaining();
+        assert!(!remaining.is_empty());
+        assert!(remaining.iter().all(|event| event.direction == CaptureDirection::Write));
+        assert!(replay.mismatches().is_empty());
+        assert_eq!(replay.verify().unwrap_err().len(), remaining.len());
+    }
+
+    #[test]
+    fn malformed_captures_are_rejected() {
+        let error = Capture::parse("elgato-streamdeck capture v1 0fd9:ffff\n".as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Invalid capture header on line 1");
+
+        let capture = "elgato-streamdeck capture v1 0fd9:0080\n# comment\n\n0.1 set 0308\n0.2 sent 0308\n";
+        let error = Capture::parse(capture.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid direction on line 5");
+
+        let error = Capture::parse("elgato-streamdeck capture v1 0fd9:0080\n0.1 write 030\n".as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid report bytes on line 2");
+    }
+}
@@ -0,0 +1,350 @@
+//! Detection of several keys being pressed together
+//!
+//! Detector can be given to [DeviceStateReader](crate::DeviceStateReader) or [AsyncDeviceStateReader](crate::asynchronous::AsyncDeviceStateReader),
+//! which will then report [Chord](crate::DeviceStateUpdate::Chord) updates with index of the chord that was pressed.
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::chords::{Chord, ChordDetector, ChordKeyPolicy};
+//! use elgato_streamdeck::info::Kind;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = Arc::new(StreamDeck::connect(&hidapi, Kind::Mk2, "AL12K2C02059").unwrap());
+//! let reader = device.get_reader();
+//!
+//! let mut chords = ChordDetector::new();
+//! // Keys 0 and 4 pressed within 80 ms of each other
+//! let together = chords.add(Chord::new(&[0, 4], Some(Duration::from_millis(80)), ChordKeyPolicy::Delay));
+//! // Key 3 pressed while key 1 is held
+//! let modifier = chords.add(Chord::new(&[1, 3], None, ChordKeyPolicy::Suppress));
+//! reader.set_chords(Some(chords)).unwrap();
+//!
+//! loop {
+//!     for update in reader.read(Some(Duration::from_secs(60))).unwrap() {
+//!         match update {
+//!             DeviceStateUpdate::Chord(index) if index == together => println!("0 + 4"),
+//!             DeviceStateUpdate::Chord(index) if index == modifier => println!("1 held + 3"),
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::DeviceStateUpdate;
+
+/// What happens to updates of individual keys that are part of a chord
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChordKeyPolicy {
+    /// Key updates are reported as usual, chord update is reported after them
+    PassThrough,
+
+    /// Key down and key up updates of the key that completed the chord are replaced with chord update.
+    /// Keys that were pressed earlier are reported as usual, which suits modifier-like chords
+    /// where one key is held before another is pressed
+    Suppress,
+
+    /// Key down updates are held back until the chord either completes or can't complete anymore.
+    /// If the chord completes, held back updates and matching key up updates are dropped,
+    /// otherwise they're reported late
+    Delay,
+}
+
+/// Combination of keys that have to be pressed together
+#[derive(Clone, Debug)]
+pub struct Chord {
+    /// Keys of the chord
+    pub keys: Vec<u8>,
+
+    /// Maximum time between first and last key of the chord being pressed, None if keys only need to be held at the same time
+    pub window: Option<Duration>,
+
+    /// What happens to updates of individual keys
+    pub policy: ChordKeyPolicy,
+}
+
+impl Chord {
+    /// Creates chord from keys, time window and policy
+    pub fn new(keys: &[u8], window: Option<Duration>, policy: ChordKeyPolicy) -> Chord {
+        Chord {
+            keys: keys.to_vec(),
+            window,
+            policy,
+        }
+    }
+}
+
+/// Turns button updates into chord updates.
+///
+/// Doesn't do any I/O, can be driven manually with [process](ChordDetector::process) and [poll](ChordDetector::poll)
+#[derive(Default)]
+pub struct ChordDetector {
+    chords: Vec<Chord>,
+    pressed_at: HashMap<u8, Instant>,
+    /// Chords that fired and still have all their keys held
+    active: HashSet<usize>,
+    /// Keys which key up updates are dropped
+    suppressed: HashSet<u8>,
+    /// Key down updates that are held back, with time they have to be reported at
+    delayed: Vec<(u8, Option<Instant>)>,
+}
+
+/// Static functions of the struct
+impl ChordDetector {
+    /// Creates detector without any chords
+    pub fn new() -> ChordDetector {
+        ChordDetector::default()
+    }
+}
+
+/// Instance methods of the struct
+impl ChordDetector {
+    /// Registers the chord and returns its index, which is reported by [Chord](DeviceStateUpdate::Chord) updates
+    pub fn add(&mut self, chord: Chord) -> usize {
+        self.chords.push(chord);
+        self.chords.len() - 1
+    }
+
+    /// Returns registered chords
+    pub fn chords(&self) -> &[Chord] {
+        &self.chords
+    }
+
+    /// Forgets all pressed keys and held back updates, without producing any updates
+    pub fn reset(&mut self) {
+        self.pressed_at.clear();
+        self.active.clear();
+        self.suppressed.clear();
+        self.delayed.clear();
+    }
+
+    /// Returns when [poll](ChordDetector::poll) has to be called next for held back updates to be reported on time
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.delayed.iter().filter_map(|(_, until)| *until).min()
+    }
+
+    /// Feeds updates that were read from the device together with current states of buttons.
+    /// Returns updates with chord updates added and key updates changed according to chord policies,
+    /// held back updates that became due before `now` come first
+    pub fn process(&mut self, updates: &[DeviceStateUpdate], buttons: &[bool], now: Instant) -> Vec<DeviceStateUpdate> {
+        let mut result = self.poll(now);
+
+        for update in updates {
+            match *update {
+                DeviceStateUpdate::ButtonDown(key) => self.key_down(key, buttons, now, &mut result),
+                DeviceStateUpdate::ButtonUp(key) => self.key_up(key, &mut result),
+
+                DeviceStateUpdate::Reconnected => {
+                    self.reset();
+                    result.push(*update);
+                }
+
+                _ => result.push(*update),
+            }
+        }
+
+        result
+    }
+
+    /// Returns held back updates that became due by `now`
+    pub fn poll(&mut self, now: Instant) -> Vec<DeviceStateUpdate> {
+        let mut result = vec![];
+
+        self.delayed.retain(|(key, until)| match until {
+            Some(until) if *until <= now => {
+                result.push(DeviceStateUpdate::ButtonDown(*key));
+                false
+            }
+
+            _ => true,
+        });
+
+        result
+    }
+
+    fn key_down(&mut self, key: u8, buttons: &[bool], now: Instant, result: &mut Vec<DeviceStateUpdate>) {
+        self.pressed_at.insert(key, now);
+
+        let is_held = |key: &u8| buttons.get(*key as usize).copied().unwrap_or(false);
+
+        let matched = self.chords.iter().enumerate().find(|(index, chord)| {
+            if self.active.contains(index) || !chord.keys.contains(&key) || !chord.keys.iter().all(is_held) {
+                return false;
+            }
+
+            match chord.window {
+                Some(window) => chord.keys.iter().all(|key| self.pressed_at.get(key).is_some_and(|at| now.duration_since(*at) <= window)),
+                None => true,
+            }
+        });
+
+        let Some((index, chord)) = matched else {
+            // Key might still become part of a chord that delays key updates
+            let delay = self
+                .chords
+                .iter()
+                .filter(|chord| chord.policy == ChordKeyPolicy::Delay && chord.keys.contains(&key))
+                .map(|chord| chord.window.unwrap_or(Duration::MAX))
+                .max();
+
+            match delay {
+                Some(Duration::MAX) => self.delayed.push((key, None)),
+                Some(window) => self.delayed.push((key, Some(now + window))),
+                None => result.push(DeviceStateUpdate::ButtonDown(key)),
+            }
+
+            return;
+        };
+
+        match chord.policy {
+            ChordKeyPolicy::PassThrough => result.push(DeviceStateUpdate::ButtonDown(key)),
+
+            ChordKeyPolicy::Suppress => {
+                self.suppressed.insert(key);
+            }
+
+            ChordKeyPolicy::Delay => {
+                let suppressed = &mut self.suppressed;
+
+                self.delayed.retain(|(delayed, _)| {
+                    if chord.keys.contains(delayed) {
+                        suppressed.insert(*delayed);
+                        false
+                    } else {
+                        true
+                    }
+                });
+
+                suppressed.insert(key);
+            }
+        }
+
+        self.active.insert(index);
+        result.push(DeviceStateUpdate::Chord(index));
+    }
+
+    fn key_up(&mut self, key: u8, result: &mut Vec<DeviceStateUpdate>) {
+        self.pressed_at.remove(&key);
+
+        let chords = &self.chords;
+        self.active.retain(|index| !chords[*index].keys.contains(&key));
+
+        if self.suppressed.remove(&key) {
+            return;
+        }
+
+        // Key was released before any chord completed, reporting everything that was held back up to it
+        if let Some(position) = self.delayed.iter().position(|(delayed, _)| *delayed == key) {
+            for (delayed, _) in self.delayed.drain(..=position) {
+                result.push(DeviceStateUpdate::ButtonDown(delayed));
+            }
+        }
+
+        result.push(DeviceStateUpdate::ButtonUp(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::info::Kind;
+    use crate::mock::{mock_reader, MockDevice};
+
+    fn detector(chords: &[Chord]) -> Option<ChordDetector> {
+        let mut detector = ChordDetector::new();
+
+        for chord in chords {
+            detector.add(chord.clone());
+        }
+
+        Some(detector)
+    }
+
+    fn hold(mock: &MockDevice, keys: &[usize]) {
+        let mut states = [false; 15];
+
+        for key in keys {
+            states[*key] = true;
+        }
+
+        mock.queue_button_states(&states);
+    }
+
+    #[test]
+    fn delayed_keys_are_replaced_by_chord() {
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_chords(detector(&[Chord::new(&[0, 4], Some(Duration::from_secs(10)), ChordKeyPolicy::Delay)])).unwrap();
+
+        hold(&mock, &[0]);
+        assert!(reader.read(None).unwrap().is_empty());
+
+        hold(&mock, &[0, 4]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::Chord(0)]));
+
+        hold(&mock, &[]);
+        assert!(reader.read(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delayed_key_is_flushed_once_window_is_over() {
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_chords(detector(&[Chord::new(&[0, 4], Some(Duration::from_millis(50)), ChordKeyPolicy::Delay)])).unwrap();
+
+        hold(&mock, &[0]);
+        assert!(reader.read(None).unwrap().is_empty());
+
+        // Reader wakes up by itself when the held back update is due
+        let updates = reader.read_timed(Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].update, DeviceStateUpdate::ButtonDown(0)));
+    }
+
+    #[test]
+    fn delayed_key_is_flushed_when_released_early() {
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_chords(detector(&[Chord::new(&[0, 4], None, ChordKeyPolicy::Delay)])).unwrap();
+
+        hold(&mock, &[0]);
+        assert!(reader.read(None).unwrap().is_empty());
+
+        hold(&mock, &[]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonDown(0), DeviceStateUpdate::ButtonUp(0)]));
+    }
+
+    #[test]
+    fn pass_through_reports_keys_and_chord() {
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_chords(detector(&[Chord::new(&[1, 2], None, ChordKeyPolicy::PassThrough)])).unwrap();
+
+        hold(&mock, &[1]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonDown(1)]));
+
+        hold(&mock, &[1, 2]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonDown(2), DeviceStateUpdate::Chord(0)]));
+
+        hold(&mock, &[]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonUp(1), DeviceStateUpdate::ButtonUp(2)]));
+    }
+
+    #[test]
+    fn suppress_hides_only_key_that_completed_chord() {
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_chords(detector(&[Chord::new(&[1, 3], None, ChordKeyPolicy::Suppress)])).unwrap();
+
+        hold(&mock, &[1]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonDown(1)]));
+
+        hold(&mock, &[1, 3]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::Chord(0)]));
+
+        hold(&mock, &[1]);
+        assert!(reader.read(None).unwrap().is_empty());
+
+        hold(&mock, &[]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonUp(1)]));
+    }
+}
@@ -0,0 +1,304 @@
+//! Absolute values for encoders, with limits, wrap-around and acceleration
+//!
+//! Tracker can be given to [DeviceStateReader](crate::DeviceStateReader) or [AsyncDeviceStateReader](crate::asynchronous::AsyncDeviceStateReader),
+//! which will then report [EncoderValue](crate::DeviceStateUpdate::EncoderValue) updates after twists that changed the value.
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::encoders::{EncoderAcceleration, EncoderConfig, EncoderTracker};
+//! use elgato_streamdeck::info::Kind;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = Arc::new(StreamDeck::connect(&hidapi, Kind::Plus, "A00WA3282KB4Q").unwrap());
+//! let reader = device.get_reader();
+//!
+//! let mut encoders = EncoderTracker::new(Kind::Plus, EncoderConfig::default());
+//! // Volume on the first dial, from 0 to 100 and faster when spun quickly
+//! encoders.set_config(0, EncoderConfig {
+//!     acceleration: Some(EncoderAcceleration::default()),
+//!     ..EncoderConfig::range(0, 100, 1)
+//! });
+//! encoders.set_value(0, 50);
+//! reader.set_encoders(Some(encoders)).unwrap();
+//!
+//! loop {
+//!     for update in reader.read(Some(Duration::from_secs(60))).unwrap() {
+//!         if let DeviceStateUpdate::EncoderValue(0, volume) = update {
+//!             println!("Volume is {}", volume);
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::info::Kind;
+use crate::DeviceStateUpdate;
+
+/// Makes the value change faster when the encoder is twisted quickly
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EncoderAcceleration {
+    /// Speed in ticks per second above which changes start getting multiplied
+    pub base_rate: f32,
+
+    /// Largest multiplier that can be applied to a change
+    pub max_multiplier: f32,
+}
+
+impl Default for EncoderAcceleration {
+    fn default() -> Self {
+        EncoderAcceleration {
+            base_rate: 10.0,
+            max_multiplier: 8.0,
+        }
+    }
+}
+
+/// Describes how twists change value of the encoder
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EncoderConfig {
+    /// Smallest value of the encoder
+    pub min: i32,
+
+    /// Largest value of the encoder
+    pub max: i32,
+
+    /// How much a single tick changes the value
+    pub step: i32,
+
+    /// If going past one end of the range should continue from the other end, values are clamped otherwise
+    pub wrap: bool,
+
+    /// Acceleration applied to quick twists, None to always change value by step per tick
+    pub acceleration: Option<EncoderAcceleration>,
+}
+
+impl EncoderConfig {
+    /// Creates config for a range without wrap-around or acceleration
+    pub fn range(min: i32, max: i32, step: i32) -> EncoderConfig {
+        EncoderConfig {
+            min,
+            max,
+            step,
+            wrap: false,
+            acceleration: None,
+        }
+    }
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig::range(0, 100, 1)
+    }
+}
+
+/// Keeps absolute value of every encoder of the device.
+///
+/// Doesn't do any I/O, can be driven manually with [process](EncoderTracker::process)
+pub struct EncoderTracker {
+    encoders: Vec<EncoderModel>,
+}
+
+struct EncoderModel {
+    config: EncoderConfig,
+    value: i32,
+    last_twist: Option<Instant>,
+}
+
+/// Static functions of the struct
+impl EncoderTracker {
+    /// Creates tracker for every encoder of the device kind, all using the same config and starting at minimum
+    pub fn new(kind: Kind, config: EncoderConfig) -> EncoderTracker {
+        EncoderTracker {
+            encoders: (0..kind.encoder_count())
+                .map(|_| EncoderModel {
+                    config,
+                    value: config.min,
+                    last_twist: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl EncoderTracker {
+    /// Sets config of the encoder, value is brought into the new range
+    pub fn set_config(&mut self, encoder: u8, config: EncoderConfig) {
+        if let Some(model) = self.encoders.get_mut(encoder as usize) {
+            model.config = config;
+            model.value = model.value.clamp(config.min, config.max.max(config.min));
+        }
+    }
+
+    /// Returns config of the encoder
+    pub fn config(&self, encoder: u8) -> Option<EncoderConfig> {
+        self.encoders.get(encoder as usize).map(|model| model.config)
+    }
+
+    /// Sets value of the encoder without producing any updates, for example to match value that was changed elsewhere
+    pub fn set_value(&mut self, encoder: u8, value: i32) {
+        if let Some(model) = self.encoders.get_mut(encoder as usize) {
+            model.value = value.clamp(model.config.min, model.config.max.max(model.config.min));
+        }
+    }
+
+    /// Returns value of the encoder
+    pub fn value(&self, encoder: u8) -> Option<i32> {
+        self.encoders.get(encoder as usize).map(|model| model.value)
+    }
+
+    /// Feeds updates that were read from the device.
+    /// Returns them back with value updates added after twists that changed the value
+    pub fn process(&mut self, updates: &[DeviceStateUpdate], now: Instant) -> Vec<DeviceStateUpdate> {
+        let mut result = Vec::with_capacity(updates.len());
+
+        for update in updates {
+            result.push(*update);
+
+            if let DeviceStateUpdate::EncoderTwist(encoder, ticks) = *update
+                && let Some(model) = self.encoders.get_mut(encoder as usize)
+                && model.twist(ticks, now)
+            {
+                result.push(DeviceStateUpdate::EncoderValue(encoder, model.value));
+            }
+        }
+
+        result
+    }
+}
+
+impl EncoderModel {
+    /// Applies the twist, returns true if value has changed
+    fn twist(&mut self, ticks: i8, now: Instant) -> bool {
+        let multiplier = match (self.config.acceleration, self.last_twist) {
+            (Some(acceleration), Some(last)) => {
+                // Reports can arrive back to back, not letting that look like infinite speed
+                let elapsed = now.duration_since(last).max(Duration::from_millis(1)).as_secs_f32();
+                let rate = ticks.unsigned_abs() as f32 / elapsed;
+
+                (rate / acceleration.base_rate).clamp(1.0, acceleration.max_multiplier.max(1.0))
+            }
+
+            _ => 1.0,
+        };
+
+        self.last_twist = Some(now);
+
+        let change = (ticks as f32 * self.config.step as f32 * multiplier).round() as i64;
+        let (min, max) = (self.config.min as i64, self.config.max as i64);
+        let value = self.value as i64 + change;
+
+        let value = if self.config.wrap && max >= min {
+            min + (value - min).rem_euclid(max - min + 1)
+        } else {
+            value.clamp(min, max.max(min))
+        };
+
+        let changed = value as i32 != self.value;
+        self.value = value as i32;
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_reader;
+
+    #[test]
+    fn value_is_clamped_to_range() {
+        let mut tracker = EncoderTracker::new(Kind::Plus, EncoderConfig::range(0, 10, 2));
+        tracker.set_value(1, 6);
+        let (mock, reader) = mock_reader(Kind::Plus);
+        reader.set_encoders(Some(tracker)).unwrap();
+
+        mock.queue_encoder_twist(&[0, 1, 0, 0]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::EncoderTwist(1, 1), DeviceStateUpdate::EncoderValue(1, 8)]));
+
+        mock.queue_encoder_twist(&[0, 5, 0, 0]);
+        assert!(matches!(
+            reader.read(None).unwrap()[..],
+            [DeviceStateUpdate::EncoderTwist(1, 5), DeviceStateUpdate::EncoderValue(1, 10)]
+        ));
+
+        // Value didn't change, so there's no value update
+        mock.queue_encoder_twist(&[0, 1, 0, 0]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::EncoderTwist(1, 1)]));
+
+        mock.queue_encoder_twist(&[0, -100, 0, 0]);
+        assert!(matches!(
+            reader.read(None).unwrap()[..],
+            [DeviceStateUpdate::EncoderTwist(1, -100), DeviceStateUpdate::EncoderValue(1, 0)]
+        ));
+    }
+
+    #[test]
+    fn value_wraps_around_range() {
+        let mut tracker = EncoderTracker::new(
+            Kind::Plus,
+            EncoderConfig {
+                wrap: true,
+                ..EncoderConfig::range(0, 9, 1)
+            },
+        );
+        tracker.set_value(2, 8);
+        let (mock, reader) = mock_reader(Kind::Plus);
+        reader.set_encoders(Some(tracker)).unwrap();
+
+        mock.queue_encoder_twist(&[0, 0, 3, 0]);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::EncoderTwist(2, 3), DeviceStateUpdate::EncoderValue(2, 1)]));
+
+        mock.queue_encoder_twist(&[0, 0, -2, 0]);
+        assert!(matches!(
+            reader.read(None).unwrap()[..],
+            [DeviceStateUpdate::EncoderTwist(2, -2), DeviceStateUpdate::EncoderValue(2, 9)]
+        ));
+    }
+
+    #[test]
+    fn quick_twists_are_accelerated() {
+        let mut tracker = EncoderTracker::new(
+            Kind::Plus,
+            EncoderConfig {
+                acceleration: Some(EncoderAcceleration { base_rate: 10.0, max_multiplier: 4.0 }),
+                ..EncoderConfig::range(0, 1000, 1)
+            },
+        );
+
+        let start = Instant::now();
+
+        // First twist has nothing to compare speed with
+        tracker.process(&[DeviceStateUpdate::EncoderTwist(0, 2)], start);
+        assert_eq!(tracker.value(0), Some(2));
+
+        // 2 ticks in a second is slower than base rate
+        tracker.process(&[DeviceStateUpdate::EncoderTwist(0, 2)], start + Duration::from_secs(1));
+        assert_eq!(tracker.value(0), Some(4));
+
+        // 2 ticks in 100 ms is twice the base rate
+        tracker.process(&[DeviceStateUpdate::EncoderTwist(0, 2)], start + Duration::from_millis(1100));
+        assert_eq!(tracker.value(0), Some(8));
+
+        // Multiplier never goes past the maximum
+        tracker.process(&[DeviceStateUpdate::EncoderTwist(0, 2)], start + Duration::from_millis(1101));
+        assert_eq!(tracker.value(0), Some(16));
+    }
+
+    #[test]
+    fn changing_config_brings_value_into_range() {
+        let mut tracker = EncoderTracker::new(Kind::Plus, EncoderConfig::range(0, 100, 1));
+        tracker.set_value(3, 80);
+
+        tracker.set_config(3, EncoderConfig::range(0, 50, 1));
+        assert_eq!(tracker.value(3), Some(50));
+
+        tracker.set_value(3, -5);
+        assert_eq!(tracker.value(3), Some(0));
+
+        assert_eq!(tracker.value(4), None);
+    }
+}
@@ -0,0 +1,414 @@
+//! Recognition of taps, double taps, long presses and hold repeats on keys
+//!
+//! Recognizer can be given to [DeviceStateReader](crate::DeviceStateReader) or [AsyncDeviceStateReader](crate::asynchronous::AsyncDeviceStateReader),
+//! gesture updates will then be returned by the reader in addition to regular button updates.
+//! Readers wake up by themselves when a gesture is due, even if timeout is longer than that
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::gestures::{GestureRecognizer, GestureThresholds};
+//! use elgato_streamdeck::info::Kind;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = Arc::new(StreamDeck::connect(&hidapi, Kind::Mk2, "AL12K2C02059").unwrap());
+//! let reader = device.get_reader();
+//!
+//! let mut gestures = GestureRecognizer::new(GestureThresholds::default());
+//! gestures.set_key_thresholds(0, GestureThresholds {
+//!     hold_repeat_delay: Some(Duration::from_millis(400)),
+//!     ..Default::default()
+//! });
+//! reader.set_gestures(Some(gestures)).unwrap();
+//!
+//! loop {
+//!     for update in reader.read(Some(Duration::from_secs(60))).unwrap() {
+//!         match update {
+//!             DeviceStateUpdate::KeyTap(key) => println!("Key {} tapped", key),
+//!             DeviceStateUpdate::KeyHoldRepeat(key) => println!("Key {} repeated", key),
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::DeviceStateUpdate;
+
+/// Shortest time between hold repeats, so a zero interval doesn't make the reader spin
+const MIN_HOLD_REPEAT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Timings used to tell gestures apart
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GestureThresholds {
+    /// How long the key has to be held to count as a long press
+    pub long_press: Duration,
+
+    /// How soon the key has to be pressed again after a tap to count as a double tap.
+    /// Taps are reported only after this much time passes without a second tap, None disables double taps
+    /// so taps are reported right when the key is released
+    pub double_tap: Option<Duration>,
+
+    /// How long the key has to be held before it starts repeating, None disables hold repeat
+    pub hold_repeat_delay: Option<Duration>,
+
+    /// Time between repeats while the key is held, anything shorter than a millisecond is treated as a millisecond
+    pub hold_repeat_interval: Duration,
+}
+
+impl Default for GestureThresholds {
+    fn default() -> Self {
+        GestureThresholds {
+            long_press: Duration::from_millis(500),
+            double_tap: Some(Duration::from_millis(300)),
+            hold_repeat_delay: None,
+            hold_repeat_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Turns button updates into gesture updates, keeps track of time by itself.
+///
+/// Doesn't do any I/O, can be driven manually with [process](GestureRecognizer::process) and [poll](GestureRecognizer::poll)
+pub struct GestureRecognizer {
+    default: GestureThresholds,
+    per_key: HashMap<u8, GestureThresholds>,
+    keys: HashMap<u8, KeyGesture>,
+}
+
+#[derive(Copy, Clone)]
+enum KeyGesture {
+    Held {
+        since: Instant,
+        long_pressed: bool,
+        next_repeat: Option<Instant>,
+        second_tap: bool,
+    },
+
+    WaitingForSecondTap {
+        until: Instant,
+    },
+}
+
+/// Static functions of the struct
+impl GestureRecognizer {
+    /// Creates recognizer that uses provided thresholds for all keys
+    pub fn new(default: GestureThresholds) -> GestureRecognizer {
+        GestureRecognizer {
+            default,
+            per_key: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl GestureRecognizer {
+    /// Sets thresholds for a single key, overriding the default ones
+    pub fn set_key_thresholds(&mut self, key: u8, thresholds: GestureThresholds) {
+        self.per_key.insert(key, thresholds);
+    }
+
+    /// Makes the key use default thresholds again
+    pub fn clear_key_thresholds(&mut self, key: u8) {
+        self.per_key.remove(&key);
+    }
+
+    /// Returns thresholds that are used for the key
+    pub fn key_thresholds(&self, key: u8) -> GestureThresholds {
+        self.per_key.get(&key).copied().unwrap_or(self.default)
+    }
+
+    /// Forgets all keys that are being tracked, without producing any updates
+    pub fn reset(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Returns when [poll](GestureRecognizer::poll) has to be called next for gestures to be reported on time
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.keys
+            .iter()
+            .filter_map(|(key, gesture)| {
+                let thresholds = self.key_thresholds(*key);
+
+                match *gesture {
+                    KeyGesture::Held { since, long_pressed, next_repeat, .. } => {
+                        let long_press = (!long_pressed).then(|| since + thresholds.long_press);
+                        [long_press, next_repeat].into_iter().flatten().min()
+                    }
+
+                    KeyGesture::WaitingForSecondTap { until } => Some(until),
+                }
+            })
+            .min()
+    }
+
+    /// Feeds updates that were read from the device. Returns them back together with gesture updates,
+    /// gestures that became due before `now` come first
+    pub fn process(&mut self, updates: &[DeviceStateUpdate], now: Instant) -> Vec<DeviceStateUpdate> {
+        let mut result = self.poll(now);
+
+        for update in updates {
+            result.push(*update);
+
+            match *update {
+                DeviceStateUpdate::ButtonDown(key) => self.key_down(key, now),
+                DeviceStateUpdate::ButtonUp(key) => result.extend(self.key_up(key, now)),
+                DeviceStateUpdate::Reconnected => self.reset(),
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Returns gesture updates that became due by `now`
+    pub fn poll(&mut self, now: Instant) -> Vec<DeviceStateUpdate> {
+        let mut result = vec![];
+
+        let mut keys = self.keys.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+
+        for key in keys {
+            let thresholds = self.key_thresholds(key);
+
+            match self.keys.get_mut(&key) {
+                Some(KeyGesture::Held { since, long_pressed, next_repeat, .. }) => {
+                    if !*long_pressed && now >= *since + thresholds.long_press {
+                        *long_pressed = true;
+                        result.push(DeviceStateUpdate::KeyLongPress(key));
+                    }
+
+                    if let Some(repeat) = next_repeat
+                        && now >= *repeat
+                    {
+                        result.push(DeviceStateUpdate::KeyHoldRepeat(key));
+
+                        // Not catching up on repeats that were missed because of late poll
+                        let interval = thresholds.hold_repeat_interval.max(MIN_HOLD_REPEAT_INTERVAL);
+                        *repeat = (*repeat + interval).max(now + interval);
+                    }
+                }
+
+                Some(KeyGesture::WaitingForSecondTap { until }) if now >= *until => {
+                    self.keys.remove(&key);
+                    result.push(DeviceStateUpdate::KeyTap(key));
+                }
+
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    fn key_down(&mut self, key: u8, now: Instant) {
+        let thresholds = self.key_thresholds(key);
+
+        let second_tap = matches!(self.keys.get(&key), Some(KeyGesture::WaitingForSecondTap { until }) if now < *until);
+
+        self.keys.insert(
+            key,
+            KeyGesture::Held {
+                since: now,
+                long_pressed: false,
+                next_repeat: thresholds.hold_repeat_delay.map(|delay| now + delay),
+                second_tap,
+            },
+        );
+    }
+
+    fn key_up(&mut self, key: u8, now: Instant) -> Option<DeviceStateUpdate> {
+        let thresholds = self.key_thresholds(key);
+
+        let Some(KeyGesture::Held { long_pressed, second_tap, .. }) = self.keys.remove(&key) else {
+            return None;
+        };
+
+        if long_pressed {
+            return None;
+        }
+
+        if second_tap {
+            return Some(DeviceStateUpdate::KeyDoubleTap(key));
+        }
+
+        match thresholds.double_tap {
+            Some(window) => {
+                self.keys.insert(key, KeyGesture::WaitingForSecondTap { until: now + window });
+                None
+            }
+
+            None => Some(DeviceStateUpdate::KeyTap(key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::info::Kind;
+    use crate::mock::{mock_reader, MockDevice};
+
+    fn press(mock: &MockDevice, key: usize) {
+        let mut states = [false; 15];
+        states[key] = true;
+        mock.queue_button_states(&states);
+    }
+
+    fn release(mock: &MockDevice) {
+        mock.queue_button_states(&[false; 15]);
+    }
+
+    #[test]
+    fn tap_is_reported_on_release_without_double_taps() {
+        let thresholds = GestureThresholds {
+            double_tap: None,
+            ..Default::default()
+        };
+
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_gestures(Some(GestureRecognizer::new(thresholds))).unwrap();
+
+        press(&mock, 2);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonDown(2)]));
+
+        release(&mock);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonUp(2), DeviceStateUpdate::KeyTap(2)]));
+    }
+
+    #[test]
+    fn tap_waits_for_double_tap_window() {
+        let thresholds = GestureThresholds {
+            double_tap: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_gestures(Some(GestureRecognizer::new(thresholds))).unwrap();
+
+        press(&mock, 0);
+        release(&mock);
+        reader.read(None).unwrap();
+        let released = reader.read_timed(None).unwrap()[0].timestamp;
+
+        // Reader wakes up by itself once the window is over, even though nothing else was read
+        let updates = reader.read_timed(Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].update, DeviceStateUpdate::KeyTap(0)));
+        assert!(updates[0].timestamp >= released + Duration::from_millis(50));
+        assert!(updates[0].timestamp < released + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn second_tap_within_window_is_double_tap() {
+        let thresholds = GestureThresholds {
+            double_tap: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_gestures(Some(GestureRecognizer::new(thresholds))).unwrap();
+
+        for _ in 0..2 {
+            press(&mock, 5);
+            release(&mock);
+        }
+
+        let updates = (0..4).flat_map(|_| reader.read(None).unwrap()).collect::<Vec<_>>();
+        assert!(matches!(
+            updates[..],
+            [
+                DeviceStateUpdate::ButtonDown(5),
+                DeviceStateUpdate::ButtonUp(5),
+                DeviceStateUpdate::ButtonDown(5),
+                DeviceStateUpdate::ButtonUp(5),
+                DeviceStateUpdate::KeyDoubleTap(5)
+            ]
+        ));
+    }
+
+    #[test]
+    fn long_press_is_reported_while_key_is_held() {
+        let thresholds = GestureThresholds {
+            long_press: Duration::from_millis(40),
+            double_tap: None,
+            ..Default::default()
+        };
+
+        let (mock, reader) = mock_reader(Kind::Mk2);
+        reader.set_gestures(Some(GestureRecognizer::new(thresholds))).unwrap();
+
+        press(&mock, 1);
+        let pressed = reader.read_timed(None).unwrap()[0].timestamp;
+
+        let updates = reader.read_timed(Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].update, DeviceStateUpdate::KeyLongPress(1)));
+        assert!(updates[0].timestamp >= pressed + Duration::from_millis(40));
+
+        // Releasing after a long press isn't a tap
+        release(&mock);
+        assert!(matches!(reader.read(None).unwrap()[..], [DeviceStateUpdate::ButtonUp(1)]));
+    }
+
+    #[test]
+    fn held_key_repeats_at_interval() {
+        let mut gestures = GestureRecognizer::new(GestureThresholds {
+            long_press: Duration::from_secs(10),
+            double_tap: None,
+            hold_repeat_delay: Some(Duration::from_millis(300)),
+            hold_repeat_interval: Duration::from_millis(100),
+        });
+
+        let start = Instant::now();
+        gestures.process(&[DeviceStateUpdate::ButtonDown(3)], start);
+
+        assert_eq!(gestures.next_deadline(), Some(start + Duration::from_millis(300)));
+        assert!(gestures.poll(start + Duration::from_millis(299)).is_empty());
+        assert!(matches!(gestures.poll(start + Duration::from_millis(300))[..], [DeviceStateUpdate::KeyHoldRepeat(3)]));
+        assert!(gestures.poll(start + Duration::from_millis(399)).is_empty());
+        assert!(matches!(gestures.poll(start + Duration::from_millis(400))[..], [DeviceStateUpdate::KeyHoldRepeat(3)]));
+
+        gestures.process(&[DeviceStateUpdate::ButtonUp(3)], start + Duration::from_millis(450));
+        assert_eq!(gestures.next_deadline(), None);
+    }
+
+    #[test]
+    fn late_poll_doesnt_repeat_twice_in_a_row() {
+        let mut gestures = GestureRecognizer::new(GestureThresholds {
+            long_press: Duration::from_secs(10),
+            double_tap: None,
+            hold_repeat_delay: Some(Duration::from_millis(300)),
+            hold_repeat_interval: Duration::from_millis(100),
+        });
+
+        let start = Instant::now();
+        gestures.process(&[DeviceStateUpdate::ButtonDown(3)], start);
+
+        assert!(matches!(gestures.poll(start + Duration::from_millis(550))[..], [DeviceStateUpdate::KeyHoldRepeat(3)]));
+        assert_eq!(gestures.next_deadline(), Some(start + Duration::from_millis(650)));
+        assert!(gestures.poll(start + Duration::from_millis(551)).is_empty());
+        assert!(matches!(gestures.poll(start + Duration::from_millis(650))[..], [DeviceStateUpdate::KeyHoldRepeat(3)]));
+    }
+
+    #[test]
+    fn zero_repeat_interval_is_clamped() {
+        let mut gestures = GestureRecognizer::new(GestureThresholds {
+            long_press: Duration::from_secs(10),
+            double_tap: None,
+            hold_repeat_delay: Some(Duration::ZERO),
+            hold_repeat_interval: Duration::ZERO,
+        });
+
+        let start = Instant::now();
+        gestures.process(&[DeviceStateUpdate::ButtonDown(3)], start);
+
+        assert_eq!(gestures.poll(start).len(), 1);
+        assert!(gestures.poll(start).is_empty());
+        assert_eq!(gestures.next_deadline(), Some(start + MIN_HOLD_REPEAT_INTERVAL));
+    }
+}
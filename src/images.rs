@@ -1,6 +1,4 @@
-#[allow(unused_imports)]
-use std::sync::Arc;
-use image::{ColorType, DynamicImage, GenericImageView, ImageError};
+use image::{imageops, ColorType, DynamicImage, GenericImageView, ImageError, Rgb, Rgba, RgbaImage};
 use image::codecs::bmp::BmpEncoder;
 use image::codecs::jpeg::JpegEncoder;
 use image::imageops::FilterType;
@@ -8,17 +6,75 @@ use image::imageops::FilterType;
 use crate::{Kind, StreamDeckError};
 use crate::info::{ImageFormat, ImageMirroring, ImageMode, ImageRotation};
 
+/// How image is fitted into size of the key or LCD region
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FitMode {
+    /// Image is resized to the exact size, aspect ratio isn't kept
+    #[default]
+    Stretch,
+
+    /// Image is resized to fit inside keeping aspect ratio, rest is filled with background
+    Contain,
+
+    /// Image is resized to cover everything keeping aspect ratio, parts that don't fit are cut off
+    Cover,
+
+    /// Image isn't resized and is put in the middle, rest is filled with background
+    Center,
+}
+
+/// Options for turning images into image data
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionOptions {
+    /// Filter used when resizing the image
+    pub filter: FilterType,
+
+    /// Quality of JPEG encoding, 1 - 100
+    pub quality: u8,
+
+    /// How image is fitted into the target size
+    pub fit: FitMode,
+
+    /// Color of the area that isn't covered by the image, transparent parts of the image are blended with it
+    pub background: Rgb<u8>,
+
+    /// Image that is put between background color and the image, resized to cover the whole target
+    pub background_image: Option<DynamicImage>,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            filter: FilterType::Nearest,
+            quality: 90,
+            fit: FitMode::Stretch,
+            background: Rgb([0, 0, 0]),
+            background_image: None,
+        }
+    }
+}
+
 /// Converts image into image data depending on provided kind of device
 pub fn convert_image(kind: Kind, image: DynamicImage) -> Result<Vec<u8>, ImageError> {
-    convert_image_with_format(kind.key_image_format(), image)
+    convert_image_with_options(kind, image, &ConversionOptions::default())
+}
+
+/// Converts image into image data depending on provided kind of device and conversion options
+pub fn convert_image_with_options(kind: Kind, image: DynamicImage, options: &ConversionOptions) -> Result<Vec<u8>, ImageError> {
+    convert_image_with_format_and_options(kind.key_image_format(), image, options)
 }
 
 /// Converts image into image data depending on provided image format
 pub fn convert_image_with_format(image_format: ImageFormat, image: DynamicImage) -> Result<Vec<u8>, ImageError> {
+    convert_image_with_format_and_options(image_format, image, &ConversionOptions::default())
+}
+
+/// Converts image into image data depending on provided image format and conversion options
+pub fn convert_image_with_format_and_options(image_format: ImageFormat, image: DynamicImage, options: &ConversionOptions) -> Result<Vec<u8>, ImageError> {
     // Ensuring size of the image
     let (ws, hs) = image_format.size;
 
-    let image = image.resize_exact(ws as u32, hs as u32, FilterType::Nearest);
+    let image = fit_image(image, ws as u32, hs as u32, options);
 
     // Applying rotation
     let image = match image_format.rotation {
@@ -49,28 +105,124 @@ pub fn convert_image_with_format(image_format: ImageFormat, image: DynamicImage)
         }
         ImageMode::JPEG => {
             let mut buf = Vec::new();
-            let mut encoder = JpegEncoder::new_with_quality(&mut buf, 90);
+            let mut encoder = JpegEncoder::new_with_quality(&mut buf, options.quality);
             encoder.encode(&image_data, ws as u32, hs as u32, ColorType::Rgb8.into())?;
             Ok(buf)
         }
     }
 }
 
-/// Converts image into image data depending on provided kind of device, can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+/// Brings image to the size according to fit mode of the options,
+/// composites it over the background if it doesn't cover everything or has transparency
+fn fit_image(image: DynamicImage, w: u32, h: u32, options: &ConversionOptions) -> DynamicImage {
+    let image = if image.dimensions() == (w, h) {
+        image
+    } else {
+        match options.fit {
+            FitMode::Stretch => image.resize_exact(w, h, options.filter),
+            FitMode::Cover => image.resize_to_fill(w, h, options.filter),
+            FitMode::Contain => image.resize(w, h, options.filter),
+            FitMode::Center => image,
+        }
+    };
+
+    if image.dimensions() == (w, h) && !image.color().has_alpha() {
+        return image;
+    }
+
+    let Rgb([red, green, blue]) = options.background;
+    let mut canvas = RgbaImage::from_pixel(w, h, Rgba([red, green, blue, 255]));
+
+    if let Some(background) = &options.background_image {
+        imageops::overlay(&mut canvas, &background.resize_to_fill(w, h, options.filter).into_rgba8(), 0, 0);
+    }
+
+    // Putting image in the middle, cutting off whatever doesn't fit
+    let x = (w as i64 - image.width() as i64) / 2;
+    let y = (h as i64 - image.height() as i64) / 2;
+
+    imageops::overlay(&mut canvas, &image.into_rgba8(), x, y);
+
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Converts images for several keys at once depending on provided kind of device, images are converted in parallel.
+/// Returns image data in the same order as the images were provided
+///
+/// Uses thread pool of rayon if `rayon` feature is enabled, spawns a thread per available core for the batch otherwise
+pub fn convert_images(kind: Kind, images: impl IntoIterator<Item = (u8, DynamicImage)>) -> Result<Vec<(u8, Vec<u8>)>, ImageError> {
+    convert_images_with_options(kind, images, &ConversionOptions::default())
+}
+
+/// Converts images for several keys at once in parallel like [convert_images], using provided conversion options
+pub fn convert_images_with_options(kind: Kind, images: impl IntoIterator<Item = (u8, DynamicImage)>, options: &ConversionOptions) -> Result<Vec<(u8, Vec<u8>)>, ImageError> {
+    let images = images.into_iter().collect::<Vec<_>>();
+
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+
+        images.into_par_iter().map(|(key, image)| Ok((key, convert_image_with_options(kind, image, options)?))).collect()
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get()).min(images.len());
+
+        if threads <= 1 {
+            return images.into_iter().map(|(key, image)| Ok((key, convert_image_with_options(kind, image, options)?))).collect();
+        }
+
+        let chunk_size = images.len().div_ceil(threads);
+        let mut images = images;
+
+        std::thread::scope(|scope| {
+            let mut workers = vec![];
+
+            while !images.is_empty() {
+                let rest = images.split_off(chunk_size.min(images.len()));
+                let chunk = std::mem::replace(&mut images, rest);
+
+                workers.push(scope.spawn(move || chunk.into_iter().map(|(key, image)| Ok((key, convert_image_with_options(kind, image, options)?))).collect::<Result<Vec<_>, ImageError>>()));
+            }
+
+            let mut converted = vec![];
+
+            for worker in workers {
+                converted.extend(worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))?);
+            }
+
+            Ok(converted)
+        })
+    }
+}
+
+/// Converts images for several keys at once in parallel like [convert_images], on tokio's blocking thread pool
+/// so the runtime isn't held up for the whole batch
+#[cfg(feature = "async")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub async fn convert_images_async(kind: Kind, images: impl IntoIterator<Item = (u8, DynamicImage)>) -> Result<Vec<(u8, Vec<u8>)>, StreamDeckError> {
+    let images = images.into_iter().collect::<Vec<_>>();
+
+    Ok(tokio::task::spawn_blocking(move || convert_images(kind, images)).await??)
+}
+
+/// Converts image into image data depending on provided kind of device, can be safely ran inside any runtime
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 pub fn convert_image_async(kind: Kind, image: DynamicImage) -> Result<Vec<u8>, StreamDeckError> {
-    Ok(tokio::task::block_in_place(move || convert_image(kind, image))?)
+    Ok(crate::asynchronous::run_blocking(move || convert_image(kind, image))?)
 }
 
-/// Converts image into image data depending on provided image format, can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+/// Converts image into image data depending on provided image format, can be safely ran inside any runtime
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 pub fn convert_image_with_format_async(format: ImageFormat, image: DynamicImage) -> Result<Vec<u8>, StreamDeckError> {
-    Ok(tokio::task::block_in_place(move || convert_image_with_format(format, image))?)
+    Ok(crate::asynchronous::run_blocking(move || convert_image_with_format(format, image))?)
 }
 
 /// Rect to be used when trying to send image to lcd screen
+#[derive(Clone)]
 pub struct ImageRect {
     /// Width of the image
     pub w: u16,
@@ -86,24 +238,159 @@ impl ImageRect {
     /// Converts image to image rect
     pub fn from_image(image: DynamicImage) -> Result<ImageRect, StreamDeckError> {
         let (image_w, image_h) = image.dimensions();
+        ImageRect::from_image_with_options(image, image_w as u16, image_h as u16, &ConversionOptions::default())
+    }
 
-        let image_data = image.into_rgb8().to_vec();
+    /// Converts image to image rect of provided size, image is fitted into the size according to conversion options
+    pub fn from_image_with_options(image: DynamicImage, w: u16, h: u16, options: &ConversionOptions) -> Result<ImageRect, StreamDeckError> {
+        let image_data = fit_image(image, w as u32, h as u32, options).into_rgb8().to_vec();
 
         let mut buf = Vec::new();
-        let mut encoder = JpegEncoder::new_with_quality(&mut buf, 90);
-        encoder.encode(&image_data, image_w, image_h, ColorType::Rgb8.into())?;
+        let mut encoder = JpegEncoder::new_with_quality(&mut buf, options.quality);
+        encoder.encode(&image_data, w as u32, h as u32, ColorType::Rgb8.into())?;
 
-        Ok(ImageRect {
-            w: image_w as u16,
-            h: image_h as u16,
-            data: buf,
-        })
+        Ok(ImageRect { w, h, data: buf })
     }
 
-    /// Converts image to image rect, can be safely ran inside [multi_thread](tokio::runtime::Builder::new_multi_thread) runtime
+    /// Converts image to image rect, can be safely ran inside any runtime
     #[cfg(feature = "async")]
     #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
     pub fn from_image_async(image: DynamicImage) -> Result<ImageRect, StreamDeckError> {
-        tokio::task::block_in_place(move || ImageRect::from_image(image))
+        crate::asynchronous::run_blocking(move || ImageRect::from_image(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    fn fit(image: RgbImage, w: u32, h: u32, fit: FitMode) -> RgbImage {
+        let options = ConversionOptions {
+            fit,
+            background: BLUE,
+            ..Default::default()
+        };
+
+        fit_image(DynamicImage::ImageRgb8(image), w, h, &options).into_rgb8()
+    }
+
+    /// Wide image with green edges and red middle
+    fn striped_image() -> RgbImage {
+        RgbImage::from_fn(40, 20, |x, _| if (10..30).contains(&x) { RED } else { GREEN })
+    }
+
+    #[test]
+    fn contained_image_is_letterboxed() {
+        let image = fit(RgbImage::from_pixel(40, 20, RED), 10, 10, FitMode::Contain);
+
+        assert_eq!(image.dimensions(), (10, 10));
+
+        // Image is resized to 10x5 and put in the middle
+        for (_, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(*pixel, if (2..7).contains(&y) { RED } else { BLUE }, "row {y}");
+        }
+    }
+
+    #[test]
+    fn covering_image_is_cut_off_at_the_sides() {
+        let image = fit(striped_image(), 10, 10, FitMode::Cover);
+
+        assert_eq!(image.dimensions(), (10, 10));
+        assert!(image.pixels().all(|pixel| *pixel == RED));
+    }
+
+    #[test]
+    fn stretched_image_keeps_everything() {
+        let image = fit(striped_image(), 10, 10, FitMode::Stretch);
+
+        assert_eq!(image.dimensions(), (10, 10));
+        assert_eq!(*image.get_pixel(0, 5), GREEN);
+        assert_eq!(*image.get_pixel(5, 5), RED);
+        assert_eq!(*image.get_pixel(9, 5), GREEN);
+    }
+
+    #[test]
+    fn centered_image_isnt_resized() {
+        let image = fit(RgbImage::from_pixel(4, 2, RED), 10, 10, FitMode::Center);
+
+        assert_eq!(image.dimensions(), (10, 10));
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let inside = (3..7).contains(&x) && (4..6).contains(&y);
+            assert_eq!(*pixel, if inside { RED } else { BLUE }, "pixel {x}, {y}");
+        }
+
+        // Bigger image gets cut off on every side that doesn't fit
+        let image = fit(striped_image(), 10, 10, FitMode::Center);
+
+        assert_eq!(image.dimensions(), (10, 10));
+        assert!(image.pixels().all(|pixel| *pixel == RED));
+    }
+
+    #[test]
+    fn lower_quality_gives_smaller_jpeg() {
+        let format = ImageFormat {
+            mode: ImageMode::JPEG,
+            size: (72, 72),
+            ..Default::default()
+        };
+
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(72, 72, |x, y| Rgb([(x * 7 + y * 13) as u8, (x * y) as u8, ((x ^ y) * 4) as u8])));
+
+        let convert = |quality| {
+            let options = ConversionOptions { quality, ..Default::default() };
+            convert_image_with_format_and_options(format, image.clone(), &options).unwrap()
+        };
+
+        let low = convert(10);
+        let high = convert(100);
+
+        assert!(low.len() < high.len(), "{} < {}", low.len(), high.len());
+        assert_eq!(image::load_from_memory(&low).unwrap().dimensions(), (72, 72));
+    }
+
+    /// Image with transparent left half and opaque red right half
+    fn half_transparent_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 10, |x, _| if x < 5 { Rgba([255, 255, 255, 0]) } else { Rgba([255, 0, 0, 255]) }))
+    }
+
+    #[test]
+    fn transparent_pixels_show_background_color() {
+        let options = ConversionOptions {
+            background: GREEN,
+            ..Default::default()
+        };
+
+        let image = fit_image(half_transparent_image(), 10, 10, &options).into_rgb8();
+
+        assert_eq!(*image.get_pixel(0, 0), GREEN);
+        assert_eq!(*image.get_pixel(9, 9), RED);
+    }
+
+    #[test]
+    fn transparent_pixels_show_background_image() {
+        let options = ConversionOptions {
+            background: GREEN,
+            background_image: Some(DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 40, BLUE))),
+            ..Default::default()
+        };
+
+        let image = fit_image(half_transparent_image(), 10, 10, &options).into_rgb8();
+
+        assert_eq!(*image.get_pixel(0, 0), BLUE);
+        assert_eq!(*image.get_pixel(9, 9), RED);
+
+        // Background image covers the letterbox too
+        let options = ConversionOptions { fit: FitMode::Contain, ..options };
+        let image = fit_image(DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 10, RED)), 10, 10, &options).into_rgb8();
+
+        assert_eq!(*image.get_pixel(5, 0), BLUE);
+        assert_eq!(*image.get_pixel(5, 5), RED);
     }
 }
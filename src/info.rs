@@ -165,6 +165,28 @@ impl Kind {
         }
     }
 
+    /// Width of the part of LCD strip that is above each encoder, None if the device doesn't have both
+    pub fn lcd_segment_width(&self) -> Option<usize> {
+        let (width, _) = self.lcd_strip_size()?;
+
+        match self.encoder_count() {
+            0 => None,
+            count => Some(width / count as usize),
+        }
+    }
+
+    /// Index of the encoder that the point on LCD strip is above, None if the point is outside of the strip
+    pub fn lcd_segment_at(&self, x: u16, y: u16) -> Option<u8> {
+        let (width, height) = self.lcd_strip_size()?;
+
+        if x as usize >= width || y as usize >= height {
+            return None;
+        }
+
+        // Last segment also takes pixels that are left over when the width isn't divisible by encoder count
+        Some((x as usize / self.lcd_segment_width()?).min(self.encoder_count() as usize - 1) as u8)
+    }
+
     /// Tells if the Stream Deck kind has a screen
     pub fn is_visual(&self) -> bool {
         !matches!(self, Kind::Pedal)
@@ -412,3 +434,23 @@ pub enum ImageMode {
     /// Jpeg image
     JPEG,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lcd_points_are_mapped_to_segments() {
+        assert_eq!(Kind::Plus.lcd_segment_at(0, 0), Some(0));
+        assert_eq!(Kind::Plus.lcd_segment_at(199, 99), Some(0));
+        assert_eq!(Kind::Plus.lcd_segment_at(200, 50), Some(1));
+        assert_eq!(Kind::Plus.lcd_segment_at(799, 50), Some(3));
+
+        assert_eq!(Kind::Plus.lcd_segment_at(800, 50), None);
+        assert_eq!(Kind::Plus.lcd_segment_at(100, 100), None);
+
+        // Neo has LCD, but no encoders
+        assert_eq!(Kind::Neo.lcd_segment_at(10, 10), None);
+        assert_eq!(Kind::Mk2.lcd_segment_at(10, 10), None);
+    }
+}
@@ -7,21 +7,32 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![warn(missing_docs)]
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::iter::zip;
 use std::str::Utf8Error;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::ops::Deref;
+use std::sync::mpsc::{self, channel, Receiver};
 use std::sync::{Arc, Mutex, PoisonError};
-use std::time::Duration;
-
-use crate::images::{convert_image, ImageRect};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::chords::ChordDetector;
+use crate::encoders::EncoderTracker;
+use crate::gestures::GestureRecognizer;
+use crate::images::{convert_image, convert_image_with_options, convert_images_with_options, ConversionOptions, ImageRect};
+use crate::split::{InputHalf, OutputHalf};
 use hidapi::{HidApi, HidDevice, HidError, HidResult};
 use image::{DynamicImage, ImageError};
 
 use crate::info::{is_vendor_familiar, Kind};
-use crate::util::{extract_str, flip_key_index, get_feature_report, read_button_states, read_data, read_encoder_input, read_lcd_input, send_feature_report, write_data};
+use crate::lock::{FairMutex, FairMutexGuard};
+use crate::protocol::{decode_input, encode_brightness, encode_key_image, encode_lcd_fill, encode_lcd_image, encode_reset, encode_touchpoint_color, input_report_length};
+use crate::transport::Transport;
+use crate::util::{extract_str, get_feature_report, read_data, send_feature_report, write_data};
 
 /// Various information about Stream Deck devices
 pub mod info;
@@ -29,6 +40,28 @@ pub mod info;
 pub mod util;
 /// Image processing functions
 pub mod images;
+/// Abstraction over I/O used to communicate with Stream Deck devices
+pub mod transport;
+/// Packet building and parsing for Stream Deck devices
+pub mod protocol;
+/// Recording and replaying of traffic between the crate and devices
+pub mod capture;
+/// Watcher for devices being connected and disconnected
+pub mod watcher;
+/// Stream Deck handle that reconnects to the device by itself
+pub mod reconnect;
+/// Managing several Stream Decks at once
+pub mod manager;
+/// Gesture recognition on top of button updates
+pub mod gestures;
+/// Key combination detection on top of button updates
+pub mod chords;
+/// Absolute value tracking on top of encoder updates
+pub mod encoders;
+/// Separate input and output halves of a Stream Deck
+pub mod split;
+/// Mutex that takes turns between threads
+mod lock;
 
 /// Async Stream Deck
 #[cfg(feature = "async")]
@@ -38,6 +71,31 @@ pub mod asynchronous;
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 pub use asynchronous::AsyncStreamDeck;
 
+/// Async Stream Deck that works with any executor
+#[cfg(feature = "async-agnostic")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-agnostic")))]
+pub mod agnostic;
+
+/// Text rendering for keys and LCD
+#[cfg(feature = "fonts")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fonts")))]
+pub mod text;
+
+/// SVG rendering for keys and LCD
+#[cfg(feature = "svg")]
+#[cfg_attr(docsrs, doc(cfg(feature = "svg")))]
+pub mod svg;
+
+/// Fake Stream Deck device for testing
+#[cfg(any(test, feature = "mock"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "mock")))]
+pub mod mock;
+
+/// Virtual Stream Deck for Linux
+#[cfg(all(target_os = "linux", feature = "uhid"))]
+#[cfg_attr(docsrs, doc(cfg(all(target_os = "linux", feature = "uhid"))))]
+pub mod uhid;
+
 /// Creates an instance of the HidApi
 ///
 /// Can be used if you don't want to link hidapi crate into your project
@@ -72,8 +130,9 @@ pub fn list_devices(hidapi: &HidApi) -> Vec<(Kind, String)> {
         .collect()
 }
 
-/// Type of input that the device produced
+/// Type of input that the device produced. New kinds of input can be added without a major version bump
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum StreamDeckInput {
     /// No data was passed from the device
     NoData,
@@ -95,6 +154,9 @@ pub enum StreamDeckInput {
 
     /// Touch screen received a swipe
     TouchScreenSwipe((u16, u16), (u16, u16)),
+
+    /// Device was disconnected and got reopened, everything that was held down is released
+    Reconnected,
 }
 
 impl StreamDeckInput {
@@ -105,36 +167,73 @@ impl StreamDeckInput {
 }
 
 /// Interface for a Stream Deck device
-pub struct StreamDeck {
+pub struct StreamDeck<T: Transport = HidDevice> {
     /// Kind of the device
     kind: Kind,
-    /// Connected HIDDevice
-    device: HidDevice,
-    /// Temporarily cache the image before sending it to the device
-    image_cache: RwLock<Vec<ImageCache>>,
+    /// Connected device, input is read through it. Also used for everything else if there's no separate output handle
+    device: FairMutex<T>,
+    /// Separate handle of the same device that everything except reading input goes through
+    output: Option<FairMutex<T>>,
+    /// Input that was read together with a reconnect, returned by the next read
+    delayed_input: Mutex<Option<(StreamDeckInput, Instant)>>,
+    /// Images waiting to be flushed, only the latest one is kept for every key
+    pending_images: Mutex<BTreeMap<u8, Vec<u8>>>,
+    /// Hashes of images that were last uploaded to every key, used to skip uploading the same image again
+    uploaded_images: Mutex<HashMap<u8, u64>>,
 }
 
-struct ImageCache {
-    key: u8,
-    image_data: Vec<u8>,
+fn hash_image(image_data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image_data.hash(&mut hasher);
+    hasher.finish()
 }
 
+/// How long a single read waits for data while a transport is shared between reads and writes,
+/// writes wait at most this long for a read that's in progress
+pub(crate) const READ_SLICE: Duration = Duration::from_millis(10);
+
 /// Static functions of the struct
 impl StreamDeck {
-    /// Attempts to connect to the device
+    /// Attempts to connect to the device. Device is opened a second time for writing if the platform allows it,
+    /// so reads and writes don't have to wait for each other
     pub fn connect(hidapi: &HidApi, kind: Kind, serial: &str) -> Result<StreamDeck, StreamDeckError> {
         let device = hidapi.open_serial(kind.vendor_id(), kind.product_id(), serial)?;
 
-        Ok(StreamDeck {
+        // Devices can be opened only once on some platforms, everything goes through a single handle there
+        match hidapi.open_serial(kind.vendor_id(), kind.product_id(), serial) {
+            Ok(output) => Ok(StreamDeck::with_transports(kind, device, output)),
+            Err(_) => Ok(StreamDeck::with_transport(kind, device)),
+        }
+    }
+}
+
+impl<T: Transport> StreamDeck<T> {
+    /// Creates Stream Deck interface that communicates with the device of provided kind through provided transport.
+    /// Reads and writes take turns on the transport, reads wait in short slices so writes don't wait for long
+    pub fn with_transport(kind: Kind, transport: T) -> StreamDeck<T> {
+        StreamDeck::new(kind, transport, None)
+    }
+
+    /// Creates Stream Deck interface that reads input through one transport and does everything else through the other one,
+    /// both have to lead to the same device. Reads and writes never wait for each other
+    pub fn with_transports(kind: Kind, input: T, output: T) -> StreamDeck<T> {
+        StreamDeck::new(kind, input, Some(output))
+    }
+
+    fn new(kind: Kind, device: T, output: Option<T>) -> StreamDeck<T> {
+        StreamDeck {
             kind,
-            device,
-            image_cache: RwLock::new(vec![]),
-        })
+            device: FairMutex::new(device),
+            output: output.map(FairMutex::new),
+            delayed_input: Mutex::new(None),
+            pending_images: Mutex::new(BTreeMap::new()),
+            uploaded_images: Mutex::new(HashMap::new()),
+        }
     }
 }
 
 /// Instance methods of the struct
-impl StreamDeck {
+impl<T: Transport> StreamDeck<T> {
     /// Returns kind of the Stream Deck
     pub fn kind(&self) -> Kind {
         self.kind
@@ -142,29 +241,29 @@ impl StreamDeck {
 
     /// Returns manufacturer string of the device
     pub fn manufacturer(&self) -> Result<String, StreamDeckError> {
-        Ok(self.device.get_manufacturer_string()?.unwrap_or_else(|| "Unknown".to_string()))
+        Ok(self.transport()?.manufacturer_string()?.unwrap_or_else(|| "Unknown".to_string()))
     }
 
     /// Returns product string of the device
     pub fn product(&self) -> Result<String, StreamDeckError> {
-        Ok(self.device.get_product_string()?.unwrap_or_else(|| "Unknown".to_string()))
+        Ok(self.transport()?.product_string()?.unwrap_or_else(|| "Unknown".to_string()))
     }
 
     /// Returns serial number of the device
     pub fn serial_number(&self) -> Result<String, StreamDeckError> {
         match self.kind {
             Kind::Original | Kind::Mini => {
-                let bytes = get_feature_report(&self.device, 0x03, 17)?;
+                let bytes = get_feature_report(&*self.transport()?, 0x03, 17)?;
                 Ok(extract_str(&bytes[5..])?)
             }
 
             Kind::MiniMk2 => {
-                let bytes = get_feature_report(&self.device, 0x03, 32)?;
+                let bytes = get_feature_report(&*self.transport()?, 0x03, 32)?;
                 Ok(extract_str(&bytes[5..])?)
             }
 
             _ => {
-                let bytes = get_feature_report(&self.device, 0x06, 32)?;
+                let bytes = get_feature_report(&*self.transport()?, 0x06, 32)?;
                 Ok(extract_str(&bytes[2..])?)
             }
         }
@@ -175,12 +274,12 @@ impl StreamDeck {
     pub fn firmware_version(&self) -> Result<String, StreamDeckError> {
         match self.kind {
             Kind::Original | Kind::Mini | Kind::MiniMk2 => {
-                let bytes = get_feature_report(&self.device, 0x04, 17)?;
+                let bytes = get_feature_report(&*self.transport()?, 0x04, 17)?;
                 Ok(extract_str(&bytes[5..])?)
             }
 
             _ => {
-                let bytes = get_feature_report(&self.device, 0x05, 32)?;
+                let bytes = get_feature_report(&*self.transport()?, 0x05, 32)?;
                 Ok(extract_str(&bytes[6..])?)
             }
         }
@@ -188,124 +287,94 @@ impl StreamDeck {
 
     /// Reads all possible input from Stream Deck device
     pub fn read_input(&self, timeout: Option<Duration>) -> Result<StreamDeckInput, StreamDeckError> {
-        match &self.kind {
-            Kind::Plus => {
-                let data = read_data(&self.device, 14.max(5 + self.kind.encoder_count() as usize), timeout)?;
-
-                if data[0] == 0 {
-                    return Ok(StreamDeckInput::NoData);
-                }
+        Ok(self.read_input_timed(timeout)?.0)
+    }
 
-                match &data[1] {
-                    0x0 => Ok(StreamDeckInput::ButtonStateChange(read_button_states(&self.kind, &data))),
+    /// Reads all possible input from Stream Deck device, together with the time the report was read at.
+    /// Waits for input up to timeout if it's specified, returns immediately otherwise
+    pub fn read_input_timed(&self, timeout: Option<Duration>) -> Result<(StreamDeckInput, Instant), StreamDeckError> {
+        if let Some(delayed) = self.delayed_input.lock()?.take() {
+            return Ok(delayed);
+        }
 
-                    0x2 => Ok(read_lcd_input(&data)?),
+        let Some(timeout) = timeout else {
+            return self.read_report(None);
+        };
 
-                    0x3 => Ok(read_encoder_input(&self.kind, &data)?),
+        if self.output.is_some() {
+            return self.read_report(Some(timeout));
+        }
 
-                    _ => Err(StreamDeckError::BadData),
-                }
-            }
+        // Transport is shared with writes, so it's let go of every slice to give them a turn
+        let until = Instant::now() + timeout;
 
-            _ => {
-                let data = match self.kind {
-                    Kind::Original | Kind::Mini | Kind::MiniMk2 => read_data(&self.device, 1 + self.kind.key_count() as usize, timeout),
-                    _ => read_data(&self.device, 4 + self.kind.key_count() as usize + self.kind.touchpoint_count() as usize, timeout),
-                }?;
+        loop {
+            let (input, timestamp) = self.read_report(Some(until.saturating_duration_since(Instant::now()).min(READ_SLICE)))?;
 
-                if data[0] == 0 {
-                    return Ok(StreamDeckInput::NoData);
-                }
-
-                Ok(StreamDeckInput::ButtonStateChange(read_button_states(&self.kind, &data)))
+            if !input.is_empty() || timestamp >= until {
+                return Ok((input, timestamp));
             }
         }
     }
 
-    /// Resets the device
-    pub fn reset(&self) -> Result<(), StreamDeckError> {
-        match self.kind {
-            Kind::Original | Kind::Mini | Kind::MiniMk2 => {
-                let mut buf = vec![0x0B, 0x63];
+    fn read_report(&self, timeout: Option<Duration>) -> Result<(StreamDeckInput, Instant), StreamDeckError> {
+        let device = self.device.lock()?;
 
-                buf.extend(vec![0u8; 15]);
+        // Device could've been reopened while something was being written
+        if device.take_reconnected() {
+            return Ok((StreamDeckInput::Reconnected, Instant::now()));
+        }
 
-                Ok(send_feature_report(&self.device, buf.as_slice())?)
+        let data = read_data(&*device, input_report_length(self.kind), timeout)?;
+        let timestamp = Instant::now();
+        let input = decode_input(self.kind, &data)?;
+
+        // Input that came from the device reopened during this read is returned after the reconnect is reported
+        if device.take_reconnected() {
+            if !input.is_empty() {
+                *self.delayed_input.lock()? = Some((input, timestamp));
             }
 
-            _ => {
-                let mut buf = vec![0x03, 0x02];
+            return Ok((StreamDeckInput::Reconnected, timestamp));
+        }
 
-                buf.extend(vec![0u8; 30]);
+        Ok((input, timestamp))
+    }
 
-                Ok(send_feature_report(&self.device, buf.as_slice())?)
-            }
-        }
+    /// Resets the device, images that weren't flushed yet are dropped
+    pub fn reset(&self) -> Result<(), StreamDeckError> {
+        // Reset shows the logo, so whatever was uploaded or waiting to be uploaded before is gone
+        self.pending_images.lock()?.clear();
+        self.uploaded_images.lock()?.clear();
+        Ok(send_feature_report(&*self.transport()?, encode_reset(self.kind).as_slice())?)
     }
 
     /// Sets brightness of the device, value range is 0 - 100
     pub fn set_brightness(&self, percent: u8) -> Result<(), StreamDeckError> {
-        let percent = percent.clamp(0, 100);
-
-        match self.kind {
-            Kind::Original | Kind::Mini | Kind::MiniMk2 => {
-                let mut buf = vec![0x05, 0x55, 0xaa, 0xd1, 0x01, percent];
-
-                buf.extend(vec![0u8; 11]);
-
-                Ok(send_feature_report(&self.device, buf.as_slice())?)
-            }
-
-            _ => {
-                let mut buf = vec![0x03, 0x08, percent];
-
-                buf.extend(vec![0u8; 29]);
-
-                Ok(send_feature_report(&self.device, buf.as_slice())?)
-            }
-        }
+        Ok(send_feature_report(&*self.transport()?, encode_brightness(self.kind, percent).as_slice())?)
     }
 
     fn send_image(&self, key: u8, image_data: &[u8]) -> Result<(), StreamDeckError> {
-        if key >= self.kind.key_count() {
-            return Err(StreamDeckError::InvalidKeyIndex);
-        }
+        let result = self.write_reports(encode_key_image(self.kind, key, image_data)?);
 
-        let key = if let Kind::Original = self.kind { flip_key_index(&self.kind, key) } else { key };
+        // Key shows something unknown if upload failed halfway
+        let mut uploaded = self.uploaded_images.lock()?;
 
-        if !self.kind.is_visual() {
-            return Err(StreamDeckError::NoScreen);
-        }
+        match result {
+            Ok(()) => uploaded.insert(key, hash_image(image_data)),
+            Err(_) => uploaded.remove(&key),
+        };
 
-        self.write_image_data_reports(
-            image_data,
-            WriteImageParameters::for_key(self.kind, image_data.len()),
-            |page_number, this_length, last_package| match self.kind {
-                Kind::Original => vec![0x02, 0x01, (page_number + 1) as u8, 0, if last_package { 1 } else { 0 }, key + 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-
-                Kind::Mini | Kind::MiniMk2 => vec![0x02, 0x01, page_number as u8, 0, if last_package { 1 } else { 0 }, key + 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-
-                _ => vec![
-                    0x02,
-                    0x07,
-                    key,
-                    if last_package { 1 } else { 0 },
-                    (this_length & 0xff) as u8,
-                    (this_length >> 8) as u8,
-                    (page_number & 0xff) as u8,
-                    (page_number >> 8) as u8,
-                ],
-            },
-        )?;
-        Ok(())
+        result
     }
 
     /// Writes image data to Stream Deck device, changes must be flushed with `.flush()` before
     /// they will appear on the device!
+    /// Writing the same key again before flushing replaces the image that was waiting.
+    /// Keys that don't exist or can't show images are rejected right away
     pub fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), StreamDeckError> {
-        let cache_entry = ImageCache { key, image_data: image_data.to_vec() };
-
-        self.image_cache.write()?.push(cache_entry);
+        self.check_key(key)?;
+        self.pending_images.lock()?.insert(key, image_data.to_vec());
 
         Ok(())
     }
@@ -313,109 +382,30 @@ impl StreamDeck {
     /// Writes image data to Stream Deck device's lcd strip/screen as region.
     /// Only Stream Deck Plus supports writing LCD regions, for Stream Deck Neo use write_lcd_fill
     pub fn write_lcd(&self, x: u16, y: u16, rect: &ImageRect) -> Result<(), StreamDeckError> {
-        match self.kind {
-            Kind::Plus => (),
-            _ => return Err(StreamDeckError::UnsupportedOperation),
-        }
-
-        self.write_image_data_reports(
-            rect.data.as_slice(),
-            WriteImageParameters {
-                image_report_length: 1024,
-                image_report_payload_length: 1024 - 16,
-            },
-            |page_number, this_length, last_package| {
-                vec![
-                    0x02,
-                    0x0c,
-                    (x & 0xff) as u8,
-                    (x >> 8) as u8,
-                    (y & 0xff) as u8,
-                    (y >> 8) as u8,
-                    (rect.w & 0xff) as u8,
-                    (rect.w >> 8) as u8,
-                    (rect.h & 0xff) as u8,
-                    (rect.h >> 8) as u8,
-                    if last_package { 1 } else { 0 },
-                    (page_number & 0xff) as u8,
-                    (page_number >> 8) as u8,
-                    (this_length & 0xff) as u8,
-                    (this_length >> 8) as u8,
-                    0,
-                ]
-            },
-        )
+        self.write_reports(encode_lcd_image(self.kind, x, y, rect)?)
     }
 
     /// Writes image data to Stream Deck device's lcd strip/screen as full fill
     ///
     /// You can convert your images into proper image_data like this:
-    /// ```
+    /// ```no_run
+    /// # use elgato_streamdeck::{new_hidapi, StreamDeck, info::Kind};
+    /// # let hidapi = new_hidapi().unwrap();
+    /// # let device = StreamDeck::connect(&hidapi, Kind::Neo, "").unwrap();
+    /// # let image = image::open("examples/no-place-like-localhost.jpg").unwrap();
     /// use elgato_streamdeck::images::convert_image_with_format;
-    /// let image_data = convert_image_with_format(device.kind().lcd_image_format(), image).unwrap();
+    /// let image_data = convert_image_with_format(device.kind().lcd_image_format().unwrap(), image).unwrap();
     /// device.write_lcd_fill(&image_data);
     /// ```
     pub fn write_lcd_fill(&self, image_data: &[u8]) -> Result<(), StreamDeckError> {
-        match self.kind {
-            Kind::Neo => self.write_image_data_reports(
-                image_data,
-                WriteImageParameters {
-                    image_report_length: 1024,
-                    image_report_payload_length: 1024 - 8,
-                },
-                |page_number, this_length, last_package| {
-                    vec![
-                        0x02,
-                        0x0b,
-                        0,
-                        if last_package { 1 } else { 0 },
-                        (this_length & 0xff) as u8,
-                        (this_length >> 8) as u8,
-                        (page_number & 0xff) as u8,
-                        (page_number >> 8) as u8,
-                    ]
-                },
-            ),
-
-            Kind::Plus => {
-                let (w, h) = self.kind.lcd_strip_size().unwrap();
-
-                self.write_image_data_reports(
-                    image_data,
-                    WriteImageParameters {
-                        image_report_length: 1024,
-                        image_report_payload_length: 1024 - 16,
-                    },
-                    |page_number, this_length, last_package| {
-                        vec![
-                            0x02,
-                            0x0c,
-                            0,
-                            0,
-                            0,
-                            0,
-                            (w & 0xff) as u8,
-                            (w >> 8) as u8,
-                            (h & 0xff) as u8,
-                            (h >> 8) as u8,
-                            if last_package { 1 } else { 0 },
-                            (page_number & 0xff) as u8,
-                            (page_number >> 8) as u8,
-                            (this_length & 0xff) as u8,
-                            (this_length >> 8) as u8,
-                            0,
-                        ]
-                    },
-                )
-            }
-
-            _ => Err(StreamDeckError::UnsupportedOperation),
-        }
+        self.write_reports(encode_lcd_fill(self.kind, image_data)?)
     }
 
     /// Sets button's image to blank, changes must be flushed with `.flush()` before
     /// they will appear on the device!
     pub fn clear_button_image(&self, key: u8) -> Result<(), StreamDeckError> {
+        // Image that was waiting would otherwise replace the blank one on flush
+        self.pending_images.lock()?.remove(&key);
         self.send_image(key, &self.kind.blank_image())
     }
 
@@ -436,107 +426,141 @@ impl StreamDeck {
         Ok(())
     }
 
-    /// Sets specified touch point's led strip color
-    pub fn set_touchpoint_color(&self, point: u8, red: u8, green: u8, blue: u8) -> Result<(), StreamDeckError> {
-        if point >= self.kind.touchpoint_count() {
-            return Err(StreamDeckError::InvalidTouchPointIndex);
+    /// Sets specified button's image converted with provided options, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn set_button_image_with_options(&self, key: u8, image: DynamicImage, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        let image_data = convert_image_with_options(self.kind, image, options)?;
+        self.write_image(key, &image_data)
+    }
+
+    /// Sets images of several buttons at once, images are converted in parallel with [convert_images](crate::images::convert_images).
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub fn set_button_images(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>) -> Result<(), StreamDeckError> {
+        self.set_button_images_with_options(images, &ConversionOptions::default())
+    }
+
+    /// Sets images of several buttons at once converted in parallel with provided options.
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub fn set_button_images_with_options(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        let converted = convert_images_with_options(self.kind, images, options)?;
+
+        for (key, _) in &converted {
+            self.check_key(*key)?;
         }
 
-        let mut buf = vec![0x03, 0x06];
+        self.pending_images.lock()?.extend(converted);
+        Ok(())
+    }
 
-        let touchpoint_index: u8 = point + self.kind.key_count();
-        buf.extend(vec![touchpoint_index]);
-        buf.extend(vec![red, green, blue]);
+    /// Sets specified touch point's led strip color
+    pub fn set_touchpoint_color(&self, point: u8, red: u8, green: u8, blue: u8) -> Result<(), StreamDeckError> {
+        Ok(send_feature_report(&*self.transport()?, encode_touchpoint_color(self.kind, point, red, green, blue)?.as_slice())?)
+    }
 
-        Ok(send_feature_report(&self.device, buf.as_slice())?)
+    /// Flushes the button's image to the device, sending at most one image per key.
+    /// Keys that already show the same image are skipped and their images are kept,
+    /// use [force_flush](StreamDeck::force_flush) to upload them anyway.
+    /// Returns keys that were uploaded. Images written while flushing are kept for the next flush
+    pub fn flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.flush_images(false)
     }
 
-    /// Flushes the button's image to the device
-    pub fn flush(&self) -> Result<(), StreamDeckError> {
-        if self.image_cache.read()?.is_empty() {
-            return Ok(());
-        }
+    /// Flushes the button's image to the device like [flush](StreamDeck::flush), but uploads every image
+    /// even if the key already shows it, for example after something else changed the device
+    pub fn force_flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.flush_images(true)
+    }
+
+    fn flush_images(&self, force: bool) -> Result<Vec<u8>, StreamDeckError> {
+        let pending = std::mem::take(&mut *self.pending_images.lock()?);
+        let mut flushed = Vec::with_capacity(pending.len());
+        let mut skipped = vec![];
+        let mut pending = pending.into_iter();
+
+        for (key, image_data) in pending.by_ref() {
+            if !force && self.uploaded_images.lock()?.get(&key) == Some(&hash_image(&image_data)) {
+                // Kept around, so a forced flush can still upload it
+                skipped.push((key, image_data));
+                continue;
+            }
+
+            if let Err(err) = self.send_image(key, &image_data) {
+                // Putting back images that weren't sent, unless newer ones were written in the meantime
+                let mut images = self.pending_images.lock()?;
+
+                // Failed image is only worth retrying if the device couldn't be reached, anything else would fail again
+                if let StreamDeckError::HidError(_) = err {
+                    images.entry(key).or_insert(image_data);
+                }
+
+                for (key, image_data) in pending.chain(skipped) {
+                    images.entry(key).or_insert(image_data);
+                }
+
+                return Err(err);
+            }
 
-        for image in self.image_cache.read()?.iter() {
-            self.send_image(image.key, &image.image_data)?;
+            flushed.push(key);
         }
 
-        self.image_cache.write()?.clear();
+        let mut images = self.pending_images.lock()?;
 
-        Ok(())
+        for (key, image_data) in skipped {
+            images.entry(key).or_insert(image_data);
+        }
+
+        Ok(flushed)
     }
 
     /// Returns button state reader for this device
-    pub fn get_reader(self: &Arc<Self>) -> Arc<DeviceStateReader> {
-        #[allow(clippy::arc_with_non_send_sync)]
+    pub fn get_reader(self: &Arc<Self>) -> Arc<DeviceStateReader<T>> {
         Arc::new(DeviceStateReader {
             device: self.clone(),
-            states: Mutex::new(DeviceState {
-                buttons: vec![false; self.kind.key_count() as usize + self.kind.touchpoint_count() as usize],
-                encoders: vec![false; self.kind.encoder_count() as usize],
-            }),
+            states: Mutex::new(DeviceState::new(self.kind)),
         })
     }
 
-    fn write_image_data_reports<T>(&self, image_data: &[u8], parameters: WriteImageParameters, header_fn: T) -> Result<(), StreamDeckError>
-    where
-        T: Fn(usize, usize, bool) -> Vec<u8>,
-    {
-        let image_report_length = parameters.image_report_length;
-        let image_report_payload_length = parameters.image_report_payload_length;
-
-        let mut page_number = 0;
-        let mut bytes_remaining = image_data.len();
+    /// Splits the device into input half that reads and keeps state of buttons and encoders,
+    /// and output half that writes images and changes settings. Both halves can be moved to different threads
+    pub fn split(self) -> (InputHalf<T>, OutputHalf<T>) {
+        let device = Arc::new(self);
+        (InputHalf::new(device.clone()), OutputHalf::new(device))
+    }
 
-        while bytes_remaining > 0 {
-            let this_length = bytes_remaining.min(image_report_payload_length);
-            let bytes_sent = page_number * image_report_payload_length;
+    /// Checks that the key exists and can show images
+    fn check_key(&self, key: u8) -> Result<(), StreamDeckError> {
+        if key >= self.kind.key_count() {
+            return Err(StreamDeckError::InvalidKeyIndex);
+        }
 
-            // Selecting header based on device
-            let mut buf: Vec<u8> = header_fn(page_number, this_length, this_length == bytes_remaining);
+        if !self.kind.is_visual() {
+            return Err(StreamDeckError::NoScreen);
+        }
 
-            buf.extend(&image_data[bytes_sent..bytes_sent + this_length]);
+        Ok(())
+    }
 
-            // Adding padding
-            buf.extend(vec![0u8; image_report_length - buf.len()]);
+    /// Locks the transport for sending something to the device, threads get it in the order they asked for it
+    fn transport(&self) -> Result<FairMutexGuard<'_, T>, StreamDeckError> {
+        self.output.as_ref().unwrap_or(&self.device).lock()
+    }
 
-            write_data(&self.device, &buf)?;
+    /// Writes reports while holding the transport, so reports of different images can't get mixed together
+    fn write_reports(&self, reports: Vec<Vec<u8>>) -> Result<(), StreamDeckError> {
+        let transport = self.transport()?;
 
-            bytes_remaining -= this_length;
-            page_number += 1;
+        for report in reports {
+            write_data(&*transport, &report)?;
         }
 
         Ok(())
     }
 }
 
-#[derive(Clone, Copy)]
-struct WriteImageParameters {
-    pub image_report_length: usize,
-    pub image_report_payload_length: usize,
-}
-
-impl WriteImageParameters {
-    pub fn for_key(kind: Kind, image_data_len: usize) -> Self {
-        let image_report_length = match kind {
-            Kind::Original => 8191,
-            _ => 1024,
-        };
-
-        let image_report_header_length = match kind {
-            Kind::Original | Kind::Mini | Kind::MiniMk2 => 16,
-            _ => 8,
-        };
-
-        let image_report_payload_length = match kind {
-            Kind::Original => image_data_len / 2,
-            _ => image_report_length - image_report_header_length,
-        };
-
-        Self {
-            image_report_length,
-            image_report_payload_length,
-        }
+impl<T: Transport + Send + 'static> StreamDeck<T> {
+    /// Starts reading the device on a background thread, shortcut for [spawn](DeviceStateReader::spawn) of a new reader
+    pub fn spawn_reader(self: &Arc<Self>) -> (UpdateReceiver, ReaderHandle) {
+        self.get_reader().spawn()
     }
 }
 
@@ -557,6 +581,11 @@ pub enum StreamDeckError {
     /// Tokio join error
     JoinError(tokio::task::JoinError),
 
+    #[cfg(feature = "async-agnostic")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-agnostic")))]
+    /// I/O worker thread isn't running anymore
+    WorkerStopped,
+
     /// Reader mutex was poisoned
     PoisonError,
 
@@ -618,8 +647,9 @@ impl<T> From<PoisonError<T>> for StreamDeckError {
     }
 }
 
-/// Tells what changed in button states
+/// Tells what changed in button states. New kinds of updates can be added without a major version bump
 #[derive(Copy, Clone, Debug, Hash)]
+#[non_exhaustive]
 pub enum DeviceStateUpdate {
     /// Button got pressed down
     ButtonDown(u8),
@@ -650,6 +680,85 @@ pub enum DeviceStateUpdate {
 
     /// Touch screen received a swipe
     TouchScreenSwipe((u16, u16), (u16, u16)),
+
+    /// Device was disconnected and got reopened, release updates for everything that was held down come before this
+    Reconnected,
+
+    /// Key was pressed and released, only produced when gestures are enabled
+    KeyTap(u8),
+
+    /// Key was tapped twice in quick succession, only produced when gestures are enabled
+    KeyDoubleTap(u8),
+
+    /// Key was held down for long enough, only produced when gestures are enabled
+    KeyLongPress(u8),
+
+    /// Key is still held down and should repeat its action, only produced when gestures are enabled
+    KeyHoldRepeat(u8),
+
+    /// Chord with the index was pressed, only produced when chords are enabled
+    Chord(usize),
+
+    /// Encoder's absolute value has changed, only produced when encoder tracking is enabled
+    EncoderValue(u8, i32),
+
+    /// Touch screen was short pressed above the encoder, comes after [TouchScreenPress](DeviceStateUpdate::TouchScreenPress).
+    /// Only produced when segments are enabled
+    SegmentTap(u8),
+
+    /// Touch screen was long pressed above the encoder, comes after [TouchScreenLongPress](DeviceStateUpdate::TouchScreenLongPress).
+    /// Only produced when segments are enabled
+    SegmentLongPress(u8),
+
+    /// Touch screen was swiped from above one encoder to above another, comes after [TouchScreenSwipe](DeviceStateUpdate::TouchScreenSwipe).
+    /// Only produced when segments are enabled
+    SegmentSwipe(u8, u8, SwipeDirection),
+}
+
+/// Direction in which the touch screen was swiped
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum SwipeDirection {
+    /// Swipe went mostly to the left
+    Left,
+
+    /// Swipe went mostly to the right
+    Right,
+
+    /// Swipe went mostly up
+    Up,
+
+    /// Swipe went mostly down
+    Down,
+}
+
+impl SwipeDirection {
+    /// Classifies swipe by its start and end points
+    pub fn from_points(start: (u16, u16), end: (u16, u16)) -> SwipeDirection {
+        let dx = end.0 as i32 - start.0 as i32;
+        let dy = end.1 as i32 - start.1 as i32;
+
+        if dx.abs() >= dy.abs() {
+            if dx < 0 { SwipeDirection::Left } else { SwipeDirection::Right }
+        } else if dy < 0 {
+            SwipeDirection::Up
+        } else {
+            SwipeDirection::Down
+        }
+    }
+}
+
+/// Update together with timing information
+#[derive(Copy, Clone, Debug)]
+pub struct TimedUpdate {
+    /// The update itself
+    pub update: DeviceStateUpdate,
+
+    /// When the report that caused the update was read. Updates that are produced by timers,
+    /// such as gestures, have time of the read during which they became due
+    pub timestamp: Instant,
+
+    /// How long the button or encoder was held, only present on updates that release something
+    pub held_for: Option<Duration>,
 }
 
 #[derive(Default)]
@@ -657,27 +766,56 @@ struct DeviceState {
     /// Buttons include Touch Points state
     pub buttons: Vec<bool>,
     pub encoders: Vec<bool>,
+    /// When buttons and encoders got pressed, until their release is reported
+    pub buttons_pressed_at: Vec<Option<Instant>>,
+    pub encoders_pressed_at: Vec<Option<Instant>>,
+    pub chords: Option<ChordDetector>,
+    pub encoder_values: Option<EncoderTracker>,
+    pub gestures: Option<GestureRecognizer>,
+    /// If touch screen updates should be followed by updates about encoder segments
+    pub segments: bool,
 }
 
-/// Button reader that keeps state of the Stream Deck and returns events instead of full states
-pub struct DeviceStateReader {
-    device: Arc<StreamDeck>,
-    states: Mutex<DeviceState>,
-}
+impl DeviceState {
+    /// Creates state of the device where nothing is pressed
+    fn new(kind: Kind) -> DeviceState {
+        DeviceState {
+            buttons: vec![false; kind.key_count() as usize + kind.touchpoint_count() as usize],
+            encoders: vec![false; kind.encoder_count() as usize],
+            buttons_pressed_at: vec![None; kind.key_count() as usize + kind.touchpoint_count() as usize],
+            encoders_pressed_at: vec![None; kind.encoder_count() as usize],
+            chords: None,
+            encoder_values: None,
+            gestures: None,
+            segments: false,
+        }
+    }
 
-impl DeviceStateReader {
-    /// Reads states and returns updates
-    pub fn read(&self, timeout: Option<Duration>) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
-        let input = self.device.read_input(timeout)?;
-        let mut my_states = self.states.lock()?;
+    /// Returns when the state has to be updated next for time based updates to be on time
+    fn next_deadline(&self) -> Option<Instant> {
+        let chords = self.chords.as_ref().and_then(|chords| chords.next_deadline());
+        let gestures = self.gestures.as_ref().and_then(|gestures| gestures.next_deadline());
 
+        chords.into_iter().chain(gestures).min()
+    }
+
+    /// Shortens timeout of a read, so the read wakes up early if a gesture or held back update becomes due before timeout runs out
+    fn read_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.next_deadline()) {
+            (Some(timeout), Some(deadline)) => Some(timeout.min(deadline.saturating_duration_since(Instant::now()))),
+            (timeout, _) => timeout,
+        }
+    }
+
+    /// Applies input that was read at the timestamp to the state and returns what changed
+    fn update(&mut self, kind: Kind, input: StreamDeckInput, timestamp: Instant) -> Vec<TimedUpdate> {
         let mut updates = vec![];
 
         match input {
             StreamDeckInput::ButtonStateChange(buttons) => {
-                for (index, (their, mine)) in zip(buttons.iter(), my_states.buttons.iter()).enumerate() {
+                for (index, (their, mine)) in zip(buttons.iter(), self.buttons.iter()).enumerate() {
                     if their != mine {
-                        let key_count = self.device.kind.key_count();
+                        let key_count = kind.key_count();
                         if index < key_count as usize {
                             if *their {
                                 updates.push(DeviceStateUpdate::ButtonDown(index as u8));
@@ -692,11 +830,11 @@ impl DeviceStateReader {
                     }
                 }
 
-                my_states.buttons = buttons;
+                self.buttons = buttons;
             }
 
             StreamDeckInput::EncoderStateChange(encoders) => {
-                for (index, (their, mine)) in zip(encoders.iter(), my_states.encoders.iter()).enumerate() {
+                for (index, (their, mine)) in zip(encoders.iter(), self.encoders.iter()).enumerate() {
                     if *their != *mine {
                         if *their {
                             updates.push(DeviceStateUpdate::EncoderDown(index as u8));
@@ -706,7 +844,7 @@ impl DeviceStateReader {
                     }
                 }
 
-                my_states.encoders = encoders;
+                self.encoders = encoders;
             }
 
             StreamDeckInput::EncoderTwist(twist) => {
@@ -719,21 +857,452 @@ impl DeviceStateReader {
 
             StreamDeckInput::TouchScreenPress(x, y) => {
                 updates.push(DeviceStateUpdate::TouchScreenPress(x, y));
+
+                if self.segments
+                    && let Some(segment) = kind.lcd_segment_at(x, y)
+                {
+                    updates.push(DeviceStateUpdate::SegmentTap(segment));
+                }
             }
 
             StreamDeckInput::TouchScreenLongPress(x, y) => {
                 updates.push(DeviceStateUpdate::TouchScreenLongPress(x, y));
+
+                if self.segments
+                    && let Some(segment) = kind.lcd_segment_at(x, y)
+                {
+                    updates.push(DeviceStateUpdate::SegmentLongPress(segment));
+                }
             }
 
             StreamDeckInput::TouchScreenSwipe(s, e) => {
                 updates.push(DeviceStateUpdate::TouchScreenSwipe(s, e));
+
+                // End of the swipe can be outside of the screen, it still counts towards the closest segment
+                let clamp = |(x, y): (u16, u16)| kind.lcd_strip_size().map(|(w, h)| ((x as usize).min(w - 1) as u16, (y as usize).min(h - 1) as u16));
+
+                if self.segments
+                    && let (Some(from), Some(to)) = (clamp(s).and_then(|(x, y)| kind.lcd_segment_at(x, y)), clamp(e).and_then(|(x, y)| kind.lcd_segment_at(x, y)))
+                {
+                    updates.push(DeviceStateUpdate::SegmentSwipe(from, to, SwipeDirection::from_points(s, e)));
+                }
+            }
+
+            StreamDeckInput::Reconnected => {
+                let key_count = kind.key_count();
+
+                for (index, pressed) in self.buttons.iter_mut().enumerate() {
+                    if *pressed {
+                        if index < key_count as usize {
+                            updates.push(DeviceStateUpdate::ButtonUp(index as u8));
+                        } else {
+                            updates.push(DeviceStateUpdate::TouchPointUp(index as u8 - key_count));
+                        }
+
+                        *pressed = false;
+                    }
+                }
+
+                for (index, pressed) in self.encoders.iter_mut().enumerate() {
+                    if *pressed {
+                        updates.push(DeviceStateUpdate::EncoderUp(index as u8));
+                        *pressed = false;
+                    }
+                }
+
+                updates.push(DeviceStateUpdate::Reconnected);
             }
 
             _ => {}
         }
 
+        // Reports can carry more states than the device has buttons, those aren't timed
+        for update in &updates {
+            let pressed_at = match *update {
+                DeviceStateUpdate::ButtonDown(key) => self.buttons_pressed_at.get_mut(key as usize),
+                DeviceStateUpdate::TouchPointDown(point) => self.buttons_pressed_at.get_mut((kind.key_count() + point) as usize),
+                DeviceStateUpdate::EncoderDown(encoder) => self.encoders_pressed_at.get_mut(encoder as usize),
+                _ => None,
+            };
+
+            if let Some(pressed_at) = pressed_at {
+                *pressed_at = Some(timestamp);
+            }
+        }
+
+        if let Some(chords) = &mut self.chords {
+            updates = chords.process(&updates, &self.buttons, timestamp);
+        }
+
+        if let Some(encoder_values) = &mut self.encoder_values {
+            updates = encoder_values.process(&updates, timestamp);
+        }
+
+        if let Some(gestures) = &mut self.gestures {
+            updates = gestures.process(&updates, timestamp);
+        }
+
+        updates
+            .into_iter()
+            .map(|update| {
+                let pressed_at = match update {
+                    DeviceStateUpdate::ButtonUp(key) => self.buttons_pressed_at.get_mut(key as usize),
+                    DeviceStateUpdate::TouchPointUp(point) => self.buttons_pressed_at.get_mut((kind.key_count() + point) as usize),
+                    DeviceStateUpdate::EncoderUp(encoder) => self.encoders_pressed_at.get_mut(encoder as usize),
+                    _ => None,
+                };
+
+                TimedUpdate {
+                    update,
+                    timestamp,
+                    held_for: pressed_at.and_then(Option::take).map(|at| timestamp.saturating_duration_since(at)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Button reader that keeps state of the Stream Deck and returns events instead of full states
+pub struct DeviceStateReader<T: Transport = HidDevice> {
+    device: Arc<StreamDeck<T>>,
+    states: Mutex<DeviceState>,
+}
+
+impl<T: Transport> DeviceStateReader<T> {
+    /// Reads states and returns updates
+    pub fn read(&self, timeout: Option<Duration>) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
+        Ok(self.read_timed(timeout)?.into_iter().map(|timed| timed.update).collect())
+    }
+
+    /// Reads states and returns updates together with time they happened at and how long things were held for
+    pub fn read_timed(&self, timeout: Option<Duration>) -> Result<Vec<TimedUpdate>, StreamDeckError> {
+        let timeout = self.states.lock()?.read_timeout(timeout);
+
+        let (input, timestamp) = self.device.read_input_timed(timeout)?;
+        let mut my_states = self.states.lock()?;
+
+        let updates = my_states.update(self.device.kind, input, timestamp);
+
         drop(my_states);
 
         Ok(updates)
     }
+
+    /// Enables gesture recognition with provided recognizer, or disables it if None
+    pub fn set_gestures(&self, gestures: Option<GestureRecognizer>) -> Result<(), StreamDeckError> {
+        self.states.lock()?.gestures = gestures;
+        Ok(())
+    }
+
+    /// Enables chord detection with provided detector, or disables it if None
+    pub fn set_chords(&self, chords: Option<ChordDetector>) -> Result<(), StreamDeckError> {
+        self.states.lock()?.chords = chords;
+        Ok(())
+    }
+
+    /// Enables encoder value tracking with provided tracker, or disables it if None
+    pub fn set_encoders(&self, encoders: Option<EncoderTracker>) -> Result<(), StreamDeckError> {
+        self.states.lock()?.encoder_values = encoders;
+        Ok(())
+    }
+
+    /// Enables or disables updates about encoder segments of the touch screen being tapped, long pressed and swiped
+    pub fn set_segments(&self, enabled: bool) -> Result<(), StreamDeckError> {
+        self.states.lock()?.segments = enabled;
+        Ok(())
+    }
+}
+
+impl<T: Transport + Send + 'static> DeviceStateReader<T> {
+    /// Starts reading the device on a background thread, updates are sent to the returned receiver.
+    /// Gestures, chords and encoder tracking of the reader apply to the updates,
+    /// reader shouldn't be read in any other way while the thread is running.
+    ///
+    /// Device is read in short slices, so images can still be written from other threads.
+    /// Reading stops when the handle is told to stop, the receiver is dropped or reading the device fails.
+    /// Both are checked after every slice, so the thread stops shortly after even if the device is idle
+    pub fn spawn(self: &Arc<Self>) -> (UpdateReceiver, ReaderHandle) {
+        let (sender, receiver) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let alive = Arc::new(());
+
+        let reader = self.clone();
+        let thread_running = running.clone();
+        let receiver_alive = Arc::downgrade(&alive);
+
+        let thread = thread::spawn(move || {
+            while thread_running.load(Ordering::Relaxed) && receiver_alive.strong_count() > 0 {
+                for timed in reader.read_timed(Some(READ_SLICE))? {
+                    if sender.send(timed.update).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        (UpdateReceiver { receiver, _alive: alive }, ReaderHandle { running, thread })
+    }
+}
+
+/// Receiver of updates read by a background reader thread, created with [spawn](DeviceStateReader::spawn).
+/// Can be used like a regular [Receiver], the thread stops reading once it's dropped
+pub struct UpdateReceiver {
+    receiver: Receiver<DeviceStateUpdate>,
+    _alive: Arc<()>,
+}
+
+impl Deref for UpdateReceiver {
+    type Target = Receiver<DeviceStateUpdate>;
+
+    fn deref(&self) -> &Receiver<DeviceStateUpdate> {
+        &self.receiver
+    }
+}
+
+impl<'a> IntoIterator for &'a UpdateReceiver {
+    type Item = DeviceStateUpdate;
+    type IntoIter = mpsc::Iter<'a, DeviceStateUpdate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.receiver.iter()
+    }
+}
+
+/// Handle of a background reader thread, created with [spawn](DeviceStateReader::spawn).
+/// Dropping the handle leaves the thread running until the receiver is dropped
+pub struct ReaderHandle {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<Result<(), StreamDeckError>>,
+}
+
+/// Instance methods of the struct
+impl ReaderHandle {
+    /// Returns true if the thread stopped reading, either because it was told to or because of an error
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Tells the thread to stop and waits for it, returns error that stopped reading earlier if there was one
+    pub fn stop(self) -> Result<(), StreamDeckError> {
+        self.running.store(false, Ordering::Relaxed);
+        self.join()
+    }
+
+    /// Waits for the thread to stop by itself, returns error that stopped reading if there was one
+    pub fn join(self) -> Result<(), StreamDeckError> {
+        self.thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_deck;
+
+    #[test]
+    fn flush_uploads_only_latest_image_of_every_key() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+
+        device.write_image(4, &[1; 100]).unwrap();
+        device.write_image(2, &[2; 100]).unwrap();
+        device.write_image(4, &[3; 100]).unwrap();
+
+        assert_eq!(device.flush().unwrap(), [2, 4]);
+        assert_eq!(mock.output_reports().len(), 2);
+        assert_eq!(mock.key_image(2), Some(vec![2; 100]));
+        assert_eq!(mock.key_image(4), Some(vec![3; 100]));
+
+        assert_eq!(device.flush().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn images_that_are_already_shown_are_skipped() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        assert_eq!(device.flush().unwrap(), [0]);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        device.write_image(1, &[2; 100]).unwrap();
+        assert_eq!(device.flush().unwrap(), [1]);
+        assert_eq!(mock.output_reports().len(), 2);
+
+        // Skipped image can still be forced onto the device
+        assert_eq!(device.force_flush().unwrap(), [0]);
+        assert_eq!(mock.output_reports().len(), 3);
+        assert_eq!(device.force_flush().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn images_are_uploaded_again_after_reset() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        device.flush().unwrap();
+        device.reset().unwrap();
+
+        device.write_image(0, &[1; 100]).unwrap();
+        assert_eq!(device.flush().unwrap(), [0]);
+        assert_eq!(mock.output_reports().len(), 2);
+    }
+
+    #[test]
+    fn images_skipped_before_reset_arent_uploaded_after_it() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        device.flush().unwrap();
+
+        // Skipped image stays pending until reset
+        device.write_image(0, &[1; 100]).unwrap();
+        assert_eq!(device.flush().unwrap(), Vec::<u8>::new());
+        device.write_image(1, &[2; 100]).unwrap();
+
+        device.reset().unwrap();
+        assert_eq!(device.flush().unwrap(), Vec::<u8>::new());
+        assert_eq!(mock.output_reports().len(), 1);
+    }
+
+    #[test]
+    fn updates_tell_how_long_buttons_were_held() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        let reader = Arc::new(device).get_reader();
+
+        mock.queue_button_states(&[false, true]);
+        let down = reader.read_timed(None).unwrap();
+        assert_eq!(down.len(), 1);
+        assert!(matches!(down[0].update, DeviceStateUpdate::ButtonDown(1)));
+        assert_eq!(down[0].held_for, None);
+
+        thread::sleep(Duration::from_millis(20));
+
+        mock.queue_button_states(&[false, false]);
+        let up = reader.read_timed(None).unwrap();
+        assert_eq!(up.len(), 1);
+        assert!(matches!(up[0].update, DeviceStateUpdate::ButtonUp(1)));
+        assert_eq!(up[0].held_for, Some(up[0].timestamp - down[0].timestamp));
+        assert!(up[0].held_for.unwrap() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn extra_states_in_plus_button_report_dont_panic() {
+        let (mock, device) = mock_deck(Kind::Plus);
+        let reader = Arc::new(device).get_reader();
+
+        // Plus reports 10 states for its 8 keys
+        let mut states = [false; 10];
+        mock.queue_button_states(&states);
+        reader.read(None).unwrap();
+
+        states[8] = true;
+        mock.queue_button_states(&states);
+        reader.read(None).unwrap();
+
+        states[8] = false;
+        mock.queue_button_states(&states);
+        let updates = reader.read_timed(None).unwrap();
+        assert!(updates.iter().all(|timed| timed.held_for.is_none()));
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected_before_flush() {
+        let (mock, device) = mock_deck(Kind::Mini);
+
+        assert!(matches!(device.write_image(99, &[0; 10]), Err(StreamDeckError::InvalidKeyIndex)));
+
+        let image = convert_image(Kind::Mini, DynamicImage::new_rgb8(80, 80)).unwrap();
+        device.write_image(1, &image).unwrap();
+        assert_eq!(device.flush().unwrap(), [1]);
+        assert_eq!(mock.key_image(1), Some(image));
+
+        let (_, pedal) = mock_deck(Kind::Pedal);
+        assert!(matches!(pedal.write_image(0, &[0; 10]), Err(StreamDeckError::NoScreen)));
+    }
+
+    #[test]
+    fn images_that_failed_to_upload_are_kept_for_next_flush() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        mock.set_disconnected(true);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        device.write_image(1, &[2; 100]).unwrap();
+        assert!(matches!(device.flush(), Err(StreamDeckError::HidError(_))));
+
+        // Newer image written while the device was unplugged wins over the one that failed
+        device.write_image(0, &[3; 100]).unwrap();
+        mock.set_disconnected(false);
+
+        assert_eq!(device.flush().unwrap(), [0, 1]);
+        assert_eq!(mock.key_image(0), Some(vec![3; 100]));
+        assert_eq!(mock.key_image(1), Some(vec![2; 100]));
+    }
+
+    #[test]
+    fn spawned_reader_sends_updates_until_stopped() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        let (receiver, handle) = Arc::new(device).spawn_reader();
+
+        mock.queue_button_states(&[false, true]);
+        assert!(matches!(receiver.recv_timeout(Duration::from_secs(1)), Ok(DeviceStateUpdate::ButtonDown(1))));
+
+        handle.stop().unwrap();
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn spawned_reader_stops_when_receiver_is_dropped() {
+        let (_mock, device) = mock_deck(Kind::Mk2);
+        let device = Arc::new(device);
+        let (receiver, handle) = device.spawn_reader();
+
+        // Nothing is ever read, so the thread has to notice the receiver is gone by itself
+        drop(receiver);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(handle.is_finished());
+        handle.join().unwrap();
+        assert_eq!(Arc::strong_count(&device), 1);
+    }
+
+    #[test]
+    fn joining_spawned_reader_returns_read_error() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        let (_receiver, handle) = Arc::new(device).spawn_reader();
+
+        mock.set_disconnected(true);
+        assert!(matches!(handle.join(), Err(StreamDeckError::HidError(_))));
+    }
+
+    #[test]
+    fn swipe_direction_follows_larger_movement() {
+        assert_eq!(SwipeDirection::from_points((100, 50), (300, 60)), SwipeDirection::Right);
+        assert_eq!(SwipeDirection::from_points((300, 50), (100, 40)), SwipeDirection::Left);
+        assert_eq!(SwipeDirection::from_points((100, 80), (110, 10)), SwipeDirection::Up);
+        assert_eq!(SwipeDirection::from_points((100, 10), (90, 80)), SwipeDirection::Down);
+
+        // Diagonal counts as horizontal
+        assert_eq!(SwipeDirection::from_points((0, 0), (50, 50)), SwipeDirection::Right);
+    }
+
+    #[test]
+    fn swipe_ending_off_screen_counts_towards_closest_segment() {
+        let (mock, device) = mock_deck(Kind::Plus);
+        let reader = Arc::new(device).get_reader();
+        reader.set_segments(true).unwrap();
+
+        mock.queue_touchscreen_swipe((250, 50), (1000, 300));
+        let updates = reader.read(None).unwrap();
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[1], DeviceStateUpdate::SegmentSwipe(1, 3, SwipeDirection::Right)));
+
+        mock.queue_touchscreen_swipe((650, 50), (0, 50));
+        let updates = reader.read(None).unwrap();
+        assert!(matches!(updates[1], DeviceStateUpdate::SegmentSwipe(3, 0, SwipeDirection::Left)));
+    }
 }
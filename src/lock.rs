@@ -0,0 +1,122 @@
+//! Mutex that takes turns between threads
+
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+use crate::StreamDeckError;
+
+/// Mutex that lets threads through in the order they started waiting, so back to back reads
+/// can't keep writes waiting and back to back writes can't keep reads waiting
+pub(crate) struct FairMutex<T> {
+    /// Next ticket to give out and ticket of the thread whose turn it is
+    queue: Mutex<(u64, u64)>,
+    turn_changed: Condvar,
+    value: Mutex<T>,
+}
+
+/// Static functions of the struct
+impl<T> FairMutex<T> {
+    pub(crate) fn new(value: T) -> FairMutex<T> {
+        FairMutex {
+            queue: Mutex::new((0, 0)),
+            turn_changed: Condvar::new(),
+            value: Mutex::new(value),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl<T> FairMutex<T> {
+    /// Waits for the turn of the current thread and locks the value
+    pub(crate) fn lock(&self) -> Result<FairMutexGuard<'_, T>, StreamDeckError> {
+        {
+            // Queue only holds counters, so it's fine to keep using it after a panic
+            let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+            let ticket = queue.0;
+            queue.0 += 1;
+
+            while queue.1 != ticket {
+                queue = self.turn_changed.wait(queue).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+
+        // Turn is passed on when this is dropped, even if locking the value fails
+        let turn = Turn { mutex: self };
+
+        Ok(FairMutexGuard {
+            value: self.value.lock()?,
+            _turn: turn,
+        })
+    }
+}
+
+/// Locked value of [FairMutex], lets the next thread in when dropped
+pub(crate) struct FairMutexGuard<'a, T> {
+    // Value is unlocked before the turn is passed on, fields are dropped in order
+    value: MutexGuard<'a, T>,
+    _turn: Turn<'a, T>,
+}
+
+impl<T> Deref for FairMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+struct Turn<'a, T> {
+    mutex: &'a FairMutex<T>,
+}
+
+impl<T> Drop for Turn<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.queue.lock().unwrap_or_else(PoisonError::into_inner).1 += 1;
+        self.mutex.turn_changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn threads_get_their_turn_in_order_they_started_waiting() {
+        let mutex = Arc::new(FairMutex::new(()));
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let guard = mutex.lock().unwrap();
+
+        let threads = (0..5)
+            .map(|index| {
+                let thread = {
+                    let mutex = mutex.clone();
+                    let order = order.clone();
+
+                    thread::spawn(move || {
+                        let _guard = mutex.lock().unwrap();
+                        order.lock().unwrap().push(index);
+                    })
+                };
+
+                // Next thread starts only once this one took its ticket
+                while mutex.queue.lock().unwrap().0 < index + 2 {
+                    thread::yield_now();
+                }
+
+                thread
+            })
+            .collect::<Vec<_>>();
+
+        drop(guard);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+}
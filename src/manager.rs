@@ -0,0 +1,275 @@
+//! Manager that owns several Stream Decks and reads input from all of them
+//!
+//! ```no_run
+//! use elgato_streamdeck::new_hidapi;
+//! use elgato_streamdeck::manager::DeckManager;
+//! use std::time::Duration;
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let mut manager = DeckManager::new(Duration::from_millis(10));
+//!
+//! for (serial, err) in manager.open_all(&hidapi) {
+//!     eprintln!("Failed to open {}: {}", serial, err);
+//! }
+//!
+//! for (serial, timed) in manager.events() {
+//!     println!("{}: {:?}", serial, timed.update);
+//!
+//!     if let Some(deck) = manager.get(&serial) {
+//!         deck.set_brightness(100).unwrap();
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use hidapi::{HidApi, HidDevice};
+
+use crate::info::Kind;
+use crate::transport::Transport;
+use crate::{list_devices, DeviceState, StreamDeck, StreamDeckError, TimedUpdate};
+
+/// Owns several Stream Decks, each one read by its own thread.
+/// Updates from all devices are delivered through a single channel together with serial number of the device and timing information.
+/// Devices can be written to from any thread while they're being read
+pub struct DeckManager<T: Transport + Send + 'static = HidDevice> {
+    poll_interval: Duration,
+    decks: HashMap<String, ManagedDeck<T>>,
+    sender: Sender<(String, TimedUpdate)>,
+    receiver: Receiver<(String, TimedUpdate)>,
+}
+
+struct ManagedDeck<T: Transport + Send + 'static> {
+    device: Arc<StreamDeck<T>>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<Result<(), StreamDeckError>>>,
+}
+
+/// Static functions of the struct
+impl<T: Transport + Send + 'static> DeckManager<T> {
+    /// Creates manager without any devices. Reads of each device wait for input up to `poll_interval`
+    /// before checking if the device was removed, input is delivered as soon as it's read
+    pub fn new(poll_interval: Duration) -> DeckManager<T> {
+        let (sender, receiver) = channel();
+
+        DeckManager {
+            poll_interval,
+            decks: HashMap::new(),
+            sender,
+            receiver,
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl DeckManager {
+    /// Opens every device returned by [list_devices] that isn't managed yet.
+    /// Returns serial numbers of devices that failed to open together with the errors
+    pub fn open_all(&mut self, hidapi: &HidApi) -> Vec<(String, StreamDeckError)> {
+        let mut failed = vec![];
+
+        for (kind, serial) in list_devices(hidapi) {
+            if self.decks.contains_key(&serial) {
+                continue;
+            }
+
+            if let Err(err) = self.open(hidapi, kind, &serial) {
+                failed.push((serial, err));
+            }
+        }
+
+        failed
+    }
+
+    /// Opens the device and starts reading its input, replaces the device if one with the same serial is already managed
+    pub fn open(&mut self, hidapi: &HidApi, kind: Kind, serial: &str) -> Result<(), StreamDeckError> {
+        let device = StreamDeck::connect(hidapi, kind, serial)?;
+        self.insert(serial, device);
+        Ok(())
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport + Send + 'static> DeckManager<T> {
+    /// Takes ownership of an already connected device and starts reading its input
+    pub fn insert(&mut self, serial: &str, device: StreamDeck<T>) {
+        self.remove(serial);
+
+        let device = Arc::new(device);
+        let running = Arc::new(AtomicBool::new(true));
+
+        let worker = {
+            let serial = serial.to_string();
+            let device = device.clone();
+            let running = running.clone();
+            let sender = self.sender.clone();
+            let poll_interval = self.poll_interval;
+
+            thread::spawn(move || read_device(serial, device, running, sender, poll_interval))
+        };
+
+        self.decks.insert(
+            serial.to_string(),
+            ManagedDeck {
+                device,
+                running,
+                worker: Some(worker),
+            },
+        );
+    }
+
+    /// Stops reading the device and returns it, if it was managed
+    pub fn remove(&mut self, serial: &str) -> Option<Arc<StreamDeck<T>>> {
+        let mut deck = self.decks.remove(serial)?;
+        deck.stop();
+        Some(deck.device.clone())
+    }
+
+    /// Returns device with the serial number, which can be used to write images or change other settings
+    pub fn get(&self, serial: &str) -> Option<Arc<StreamDeck<T>>> {
+        self.decks.get(serial).map(|deck| deck.device.clone())
+    }
+
+    /// Returns kind and serial number of every managed device
+    pub fn devices(&self) -> Vec<(Kind, String)> {
+        self.decks.iter().map(|(serial, deck)| (deck.device.kind(), serial.clone())).collect()
+    }
+
+    /// Returns serial numbers of devices that stopped being read because reading them failed together with the errors,
+    /// usually because they were disconnected. Every failure is returned once, such devices stay managed until they're removed
+    pub fn failed(&mut self) -> Vec<(String, StreamDeckError)> {
+        let mut failed = vec![];
+
+        for (serial, deck) in &mut self.decks {
+            if !deck.worker.as_ref().is_some_and(|worker| worker.is_finished()) {
+                continue;
+            }
+
+            if let Some(Err(err)) = deck.worker.take().map(join_worker) {
+                failed.push((serial.clone(), err));
+            }
+        }
+
+        failed
+    }
+
+    /// Returns receiver of updates from all devices, each update comes with serial number of the device
+    /// and time it happened at
+    pub fn events(&self) -> &Receiver<(String, TimedUpdate)> {
+        &self.receiver
+    }
+}
+
+impl<T: Transport + Send + 'static> ManagedDeck<T> {
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<T: Transport + Send + 'static> Drop for ManagedDeck<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn join_worker(worker: JoinHandle<Result<(), StreamDeckError>>) -> Result<(), StreamDeckError> {
+    worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn read_device<T: Transport + Send + 'static>(
+    serial: String,
+    device: Arc<StreamDeck<T>>,
+    running: Arc<AtomicBool>,
+    sender: Sender<(String, TimedUpdate)>,
+    poll_interval: Duration,
+) -> Result<(), StreamDeckError> {
+    let kind = device.kind();
+    let mut state = DeviceState::new(kind);
+
+    while running.load(Ordering::Relaxed) {
+        // Device takes turns between reads and writes by itself, so writes from other threads don't wait for the whole read
+        let (input, timestamp) = device.read_input_timed(Some(poll_interval))?;
+
+        for timed in state.update(kind, input, timestamp) {
+            if sender.send((serial.clone(), timed)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::mock::{mock_deck, MockDevice};
+    use crate::DeviceStateUpdate;
+
+    fn manager_with(serial: &str) -> (MockDevice, DeckManager<MockDevice>) {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        let mut manager = DeckManager::new(Duration::from_millis(5));
+        manager.insert(serial, device);
+        (mock, manager)
+    }
+
+    #[test]
+    fn updates_of_inserted_devices_are_delivered() {
+        let (mock, manager) = manager_with("A");
+
+        assert_eq!(manager.devices(), [(Kind::Mk2, "A".to_string())]);
+        assert!(manager.get("A").is_some());
+        assert!(manager.get("B").is_none());
+
+        mock.queue_button_states(&[false, false, true]);
+        let (serial, timed) = manager.events().recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(serial, "A");
+        assert!(matches!(timed.update, DeviceStateUpdate::ButtonDown(2)));
+    }
+
+    #[test]
+    fn removed_devices_arent_read_anymore() {
+        let (mock, mut manager) = manager_with("A");
+
+        let device = manager.remove("A").unwrap();
+        assert_eq!(Arc::strong_count(&device), 1);
+        assert!(manager.devices().is_empty());
+        assert!(manager.remove("A").is_none());
+
+        mock.queue_button_states(&[true]);
+        assert!(manager.events().recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(mock.pending_input(), 1);
+    }
+
+    #[test]
+    fn read_errors_are_reported_once() {
+        let (mock, mut manager) = manager_with("A");
+
+        mock.set_disconnected(true);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut failed = vec![];
+        while failed.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+            failed = manager.failed();
+        }
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "A");
+        assert!(matches!(failed[0].1, StreamDeckError::HidError(_)));
+
+        assert!(manager.failed().is_empty());
+        assert_eq!(manager.devices().len(), 1);
+    }
+}
@@ -0,0 +1,407 @@
+//! Fake Stream Deck that can be used in place of real hardware, for example in tests
+//!
+//! ```
+//! use elgato_streamdeck::{DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::info::Kind;
+//! use elgato_streamdeck::mock::MockDevice;
+//! use std::sync::Arc;
+//!
+//! let mock = MockDevice::new(Kind::Plus);
+//! let device = Arc::new(StreamDeck::with_transport(Kind::Plus, mock.clone()));
+//!
+//! device.set_brightness(50).unwrap();
+//! assert_eq!(&mock.feature_reports()[0][..3], &[0x03, 0x08, 50]);
+//!
+//! mock.queue_encoder_twist(&[0, 3, 0, 0]);
+//! let updates = device.get_reader().read(None).unwrap();
+//! assert!(matches!(updates[..], [DeviceStateUpdate::EncoderTwist(1, 3)]));
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use hidapi::{HidError, HidResult};
+
+use crate::info::Kind;
+use crate::protocol::{
+    encode_button_states, encode_encoder_states, encode_encoder_twist, encode_touchscreen_long_press, encode_touchscreen_press, encode_touchscreen_swipe, ImageReassembler, ImageTarget,
+};
+use crate::transport::Transport;
+
+/// In-memory Stream Deck device, records everything that was sent to it and replays queued input.
+///
+/// Clones share the same state, so one clone can be given to [StreamDeck](crate::StreamDeck)
+/// while the other is used to inspect and drive the device
+#[derive(Clone)]
+pub struct MockDevice {
+    kind: Kind,
+    shared: Arc<MockShared>,
+}
+
+struct MockShared {
+    state: Mutex<MockState>,
+    input_available: Condvar,
+}
+
+struct MockState {
+    serial_number: String,
+    firmware_version: String,
+    feature_reports: Vec<Vec<u8>>,
+    output_reports: Vec<Vec<u8>>,
+    input_reports: VecDeque<Vec<u8>>,
+    reassembler: ImageReassembler,
+    images: HashMap<ImageTarget, Vec<u8>>,
+    disconnected: bool,
+}
+
+/// Static functions of the struct
+impl MockDevice {
+    /// Creates fake device of provided kind
+    pub fn new(kind: Kind) -> MockDevice {
+        MockDevice {
+            kind,
+            shared: Arc::new(MockShared {
+                state: Mutex::new(MockState {
+                    serial_number: "MOCK00000000".to_string(),
+                    firmware_version: "1.00.000".to_string(),
+                    feature_reports: vec![],
+                    output_reports: vec![],
+                    input_reports: VecDeque::new(),
+                    reassembler: ImageReassembler::new(kind),
+                    images: HashMap::new(),
+                    disconnected: false,
+                }),
+                input_available: Condvar::new(),
+            }),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl MockDevice {
+    /// Returns kind of the fake device
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Sets serial number that the device will report
+    pub fn set_serial_number(&self, serial: &str) {
+        self.state().serial_number = serial.to_string();
+    }
+
+    /// Sets firmware version that the device will report
+    pub fn set_firmware_version(&self, version: &str) {
+        self.state().firmware_version = version.to_string();
+    }
+
+    /// Returns every feature report that was sent to the device, in order
+    pub fn feature_reports(&self) -> Vec<Vec<u8>> {
+        self.state().feature_reports.clone()
+    }
+
+    /// Returns every output report that was written to the device, in order
+    pub fn output_reports(&self) -> Vec<Vec<u8>> {
+        self.state().output_reports.clone()
+    }
+
+    /// Returns last complete image that was written to the target, as it was encoded by the crate
+    pub fn image(&self, target: ImageTarget) -> Option<Vec<u8>> {
+        self.state().images.get(&target).cloned()
+    }
+
+    /// Returns last complete image that was written to the key, as it was encoded by the crate
+    pub fn key_image(&self, key: u8) -> Option<Vec<u8>> {
+        self.image(ImageTarget::Key(key))
+    }
+
+    /// Forgets all recorded feature and output reports
+    pub fn clear_reports(&self) {
+        let mut state = self.state();
+        state.feature_reports.clear();
+        state.output_reports.clear();
+    }
+
+    /// Amount of input reports that weren't read yet
+    pub fn pending_input(&self) -> usize {
+        self.state().input_reports.len()
+    }
+
+    /// Queues raw input report that will be returned by the next read
+    pub fn queue_input(&self, report: Vec<u8>) {
+        self.state().input_reports.push_back(report);
+        self.shared.input_available.notify_all();
+    }
+
+    /// Queues input report with states of all buttons, touch points included
+    pub fn queue_button_states(&self, states: &[bool]) {
+        self.queue_input(encode_button_states(self.kind, states));
+    }
+
+    /// Queues input report with press states of encoders, only Stream Deck Plus has encoders
+    pub fn queue_encoder_states(&self, states: &[bool]) {
+        self.queue_input(encode_encoder_states(self.kind, states));
+    }
+
+    /// Queues input report with tick deltas of encoders, only Stream Deck Plus has encoders
+    pub fn queue_encoder_twist(&self, ticks: &[i8]) {
+        self.queue_input(encode_encoder_twist(self.kind, ticks));
+    }
+
+    /// Queues touch screen short press, only Stream Deck Plus has touch screen
+    pub fn queue_touchscreen_press(&self, x: u16, y: u16) {
+        self.queue_input(encode_touchscreen_press(self.kind, x, y));
+    }
+
+    /// Queues touch screen long press, only Stream Deck Plus has touch screen
+    pub fn queue_touchscreen_long_press(&self, x: u16, y: u16) {
+        self.queue_input(encode_touchscreen_long_press(self.kind, x, y));
+    }
+
+    /// Queues touch screen swipe, only Stream Deck Plus has touch screen
+    pub fn queue_touchscreen_swipe(&self, start: (u16, u16), end: (u16, u16)) {
+        self.queue_input(encode_touchscreen_swipe(self.kind, start, end));
+    }
+
+    /// Makes every report fail like it would with an unplugged device, until the device is connected again.
+    /// Reads that are waiting for input fail right away
+    pub fn set_disconnected(&self, disconnected: bool) {
+        self.state().disconnected = disconnected;
+        self.shared.input_available.notify_all();
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn connected_state(&self) -> HidResult<MutexGuard<'_, MockState>> {
+        let state = self.state();
+
+        if state.disconnected {
+            return Err(disconnected());
+        }
+
+        Ok(state)
+    }
+}
+
+impl Transport for MockDevice {
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        let state = self.connected_state()?;
+
+        let (value, offset) = match (self.kind, buf.first()) {
+            (Kind::Original | Kind::Mini | Kind::MiniMk2, Some(0x03)) => (&state.serial_number, 5),
+            (Kind::Original | Kind::Mini | Kind::MiniMk2, Some(0x04)) => (&state.firmware_version, 5),
+            (Kind::Original | Kind::Mini | Kind::MiniMk2, _) => return Err(unsupported_report(buf)),
+            (_, Some(0x06)) => (&state.serial_number, 2),
+            (_, Some(0x05)) => (&state.firmware_version, 6),
+            _ => return Err(unsupported_report(buf)),
+        };
+
+        buf[1..].fill(0);
+
+        let bytes = value.as_bytes();
+        let length = bytes.len().min(buf.len().saturating_sub(offset));
+        buf[offset..offset + length].copy_from_slice(&bytes[..length]);
+
+        Ok(buf.len())
+    }
+
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()> {
+        self.connected_state()?.feature_reports.push(payload.to_vec());
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        let mut state = self.connected_state()?;
+
+        if let Some(timeout) = timeout {
+            state = self
+                .shared
+                .input_available
+                .wait_timeout_while(state, timeout, |state| state.input_reports.is_empty() && !state.disconnected)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+
+            if state.disconnected {
+                return Err(disconnected());
+            }
+        }
+
+        match state.input_reports.pop_front() {
+            Some(report) => {
+                let length = report.len().min(buf.len());
+                buf[..length].copy_from_slice(&report[..length]);
+                Ok(length)
+            }
+
+            None => Ok(0),
+        }
+    }
+
+    fn write(&self, payload: &[u8]) -> HidResult<usize> {
+        let mut state = self.connected_state()?;
+        state.output_reports.push(payload.to_vec());
+
+        if let Some((target, image)) = state.reassembler.feed(payload) {
+            state.images.insert(target, image);
+        }
+
+        Ok(payload.len())
+    }
+
+    fn manufacturer_string(&self) -> HidResult<Option<String>> {
+        Ok(Some("Elgato".to_string()))
+    }
+
+    fn product_string(&self) -> HidResult<Option<String>> {
+        Ok(Some(format!("Stream Deck {:?}", self.kind)))
+    }
+}
+
+fn disconnected() -> HidError {
+    HidError::HidApiError {
+        message: "Mock device is disconnected".to_string(),
+    }
+}
+
+fn unsupported_report(buf: &[u8]) -> HidError {
+    HidError::HidApiError {
+        message: format!("Mock device doesn't have feature report {:?}", buf.first()),
+    }
+}
+
+/// Mock device together with Stream Deck using it, shared by tests of the crate
+#[cfg(test)]
+pub(crate) fn mock_deck(kind: Kind) -> (MockDevice, crate::StreamDeck<MockDevice>) {
+    let mock = MockDevice::new(kind);
+    (mock.clone(), crate::StreamDeck::with_transport(kind, mock))
+}
+
+/// Mock device together with state reader of Stream Deck using it, shared by tests of the crate
+#[cfg(test)]
+pub(crate) fn mock_reader(kind: Kind) -> (MockDevice, Arc<crate::DeviceStateReader<MockDevice>>) {
+    let (mock, device) = mock_deck(kind);
+    (mock, Arc::new(device).get_reader())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::images::convert_image;
+    use crate::{StreamDeck, StreamDeckInput};
+
+    #[test]
+    fn reports_serial_number_and_firmware_version() {
+        for kind in [Kind::Mini, Kind::Mk2, Kind::Plus] {
+            let mock = MockDevice::new(kind);
+            mock.set_serial_number("CL12K2C02059");
+            mock.set_firmware_version("1.01.000");
+
+            let device = StreamDeck::with_transport(kind, mock.clone());
+
+            assert_eq!(device.serial_number().unwrap(), "CL12K2C02059");
+            assert_eq!(device.firmware_version().unwrap(), "1.01.000");
+        }
+    }
+
+    #[test]
+    fn records_feature_reports_in_order() {
+        let mock = MockDevice::new(Kind::Mk2);
+        let device = StreamDeck::with_transport(Kind::Mk2, mock.clone());
+
+        device.set_brightness(10).unwrap();
+        device.reset().unwrap();
+
+        let reports = mock.feature_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(&reports[0][..3], &[0x03, 0x08, 10]);
+        assert_eq!(&reports[1][..2], &[0x03, 0x02]);
+
+        mock.clear_reports();
+        assert!(mock.feature_reports().is_empty());
+    }
+
+    #[test]
+    fn puts_written_key_images_back_together() {
+        for kind in [Kind::Original, Kind::Mini, Kind::Mk2, Kind::Xl] {
+            let mock = MockDevice::new(kind);
+            let device = StreamDeck::with_transport(kind, mock.clone());
+
+            let (width, height) = kind.key_image_format().size;
+            let image = convert_image(kind, DynamicImage::new_rgb8(width as u32, height as u32)).unwrap();
+            device.write_image(3, &image).unwrap();
+            device.flush().unwrap();
+
+            assert_eq!(mock.key_image(3), Some(image), "{:?}", kind);
+            assert_eq!(mock.key_image(2), None);
+        }
+    }
+
+    #[test]
+    fn returns_queued_input_in_order() {
+        let mock = MockDevice::new(Kind::Mini);
+        let device = StreamDeck::with_transport(Kind::Mini, mock.clone());
+
+        mock.queue_button_states(&[true, false, false, false, false, false]);
+        mock.queue_button_states(&[false; 6]);
+        assert_eq!(mock.pending_input(), 2);
+
+        assert!(matches!(device.read_input(None).unwrap(), StreamDeckInput::ButtonStateChange(states) if states[0]));
+        assert!(matches!(device.read_input(None).unwrap(), StreamDeckInput::ButtonStateChange(states) if !states[0]));
+        assert!(device.read_input(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_waits_for_timeout_when_nothing_is_queued() {
+        let mock = MockDevice::new(Kind::Mk2);
+        let device = StreamDeck::with_transport(Kind::Mk2, mock);
+
+        let started = Instant::now();
+        assert!(device.read_input(Some(Duration::from_millis(30))).unwrap().is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn read_wakes_up_when_input_is_queued() {
+        let mock = MockDevice::new(Kind::Mk2);
+        let device = StreamDeck::with_transport(Kind::Mk2, mock.clone());
+
+        let queuer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            mock.queue_button_states(&[true]);
+        });
+
+        let started = Instant::now();
+        assert!(!device.read_input(Some(Duration::from_secs(10))).unwrap().is_empty());
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        queuer.join().unwrap();
+    }
+
+    #[test]
+    fn disconnected_device_fails_until_connected_again() {
+        let mock = MockDevice::new(Kind::Mk2);
+        let device = StreamDeck::with_transport(Kind::Mk2, mock.clone());
+
+        let disconnector = {
+            let mock = mock.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                mock.set_disconnected(true);
+            })
+        };
+
+        assert!(device.read_input(Some(Duration::from_secs(10))).is_err());
+        assert!(device.set_brightness(50).is_err());
+        disconnector.join().unwrap();
+
+        mock.set_disconnected(false);
+        device.set_brightness(50).unwrap();
+        assert_eq!(mock.feature_reports().len(), 1);
+    }
+}
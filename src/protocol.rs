@@ -0,0 +1,615 @@
+//! Wire format of Stream Deck devices, without any I/O.
+//!
+//! Functions in here build reports that should be sent to the device and parse reports
+//! that were received from it, so the same format can be used with any HID stack
+
+use std::collections::HashMap;
+
+use crate::images::ImageRect;
+use crate::info::Kind;
+use crate::util::{flip_key_index, read_button_states, read_encoder_input, read_lcd_input};
+use crate::{StreamDeckError, StreamDeckInput};
+
+/// Length of input report that the device kind sends
+pub fn input_report_length(kind: Kind) -> usize {
+    match kind {
+        Kind::Plus => 14.max(5 + kind.encoder_count() as usize),
+        Kind::Original | Kind::Mini | Kind::MiniMk2 => 1 + kind.key_count() as usize,
+        _ => 4 + kind.key_count() as usize + kind.touchpoint_count() as usize,
+    }
+}
+
+/// Parses input report received from the device
+pub fn decode_input(kind: Kind, data: &[u8]) -> Result<StreamDeckInput, StreamDeckError> {
+    if data.first().copied().unwrap_or(0) == 0 {
+        return Ok(StreamDeckInput::NoData);
+    }
+
+    if data.len() < input_report_length(kind) {
+        return Err(StreamDeckError::BadData);
+    }
+
+    match kind {
+        Kind::Plus => match &data[1] {
+            0x0 => Ok(StreamDeckInput::ButtonStateChange(read_button_states(&kind, data))),
+
+            0x2 => read_lcd_input(data),
+
+            0x3 => read_encoder_input(&kind, data),
+
+            _ => Err(StreamDeckError::BadData),
+        },
+
+        _ => Ok(StreamDeckInput::ButtonStateChange(read_button_states(&kind, data))),
+    }
+}
+
+/// Builds input report with states of all buttons, touch points included
+pub fn encode_button_states(kind: Kind, states: &[bool]) -> Vec<u8> {
+    let mut report = vec![0u8; input_report_length(kind)];
+    report[0] = 0x01;
+
+    match kind {
+        Kind::Original => {
+            for (key, pressed) in states.iter().enumerate().take(kind.key_count() as usize) {
+                report[flip_key_index(&kind, key as u8) as usize + 1] = *pressed as u8;
+            }
+        }
+
+        Kind::Mini | Kind::MiniMk2 => {
+            for (index, pressed) in states.iter().enumerate().take(report.len() - 1) {
+                report[index + 1] = *pressed as u8;
+            }
+        }
+
+        _ => {
+            let count = kind.key_count() as u16 + kind.touchpoint_count() as u16;
+            report[2] = (count & 0xff) as u8;
+            report[3] = (count >> 8) as u8;
+
+            for (index, pressed) in states.iter().enumerate().take(report.len() - 4) {
+                report[index + 4] = *pressed as u8;
+            }
+        }
+    }
+
+    report
+}
+
+/// Builds input report with press states of encoders, only Stream Deck Plus has encoders
+pub fn encode_encoder_states(kind: Kind, states: &[bool]) -> Vec<u8> {
+    encode_encoder_report(kind, 0x00, states.iter().map(|s| *s as u8))
+}
+
+/// Builds input report with tick deltas of encoders, only Stream Deck Plus has encoders
+pub fn encode_encoder_twist(kind: Kind, ticks: &[i8]) -> Vec<u8> {
+    encode_encoder_report(kind, 0x01, ticks.iter().map(|t| t.to_le_bytes()[0]))
+}
+
+/// Builds input report of touch screen short press, only Stream Deck Plus has touch screen
+pub fn encode_touchscreen_press(kind: Kind, x: u16, y: u16) -> Vec<u8> {
+    encode_touchscreen_report(kind, 0x01, (x, y), (0, 0))
+}
+
+/// Builds input report of touch screen long press, only Stream Deck Plus has touch screen
+pub fn encode_touchscreen_long_press(kind: Kind, x: u16, y: u16) -> Vec<u8> {
+    encode_touchscreen_report(kind, 0x02, (x, y), (0, 0))
+}
+
+/// Builds input report of touch screen swipe, only Stream Deck Plus has touch screen
+pub fn encode_touchscreen_swipe(kind: Kind, start: (u16, u16), end: (u16, u16)) -> Vec<u8> {
+    encode_touchscreen_report(kind, 0x03, start, end)
+}
+
+fn encode_encoder_report(kind: Kind, event: u8, values: impl Iterator<Item = u8>) -> Vec<u8> {
+    let mut report = vec![0u8; input_report_length(kind)];
+    report[0] = 0x01;
+    report[1] = 0x03;
+    report[4] = event;
+
+    for (index, value) in values.take(kind.encoder_count() as usize).enumerate() {
+        report[index + 5] = value;
+    }
+
+    report
+}
+
+fn encode_touchscreen_report(kind: Kind, event: u8, (start_x, start_y): (u16, u16), (end_x, end_y): (u16, u16)) -> Vec<u8> {
+    let mut report = vec![0u8; input_report_length(kind).max(14)];
+    report[0] = 0x01;
+    report[1] = 0x02;
+    report[4] = event;
+    report[6..8].copy_from_slice(&start_x.to_le_bytes());
+    report[8..10].copy_from_slice(&start_y.to_le_bytes());
+    report[10..12].copy_from_slice(&end_x.to_le_bytes());
+    report[12..14].copy_from_slice(&end_y.to_le_bytes());
+
+    report
+}
+
+/// Builds feature report that resets the device
+pub fn encode_reset(kind: Kind) -> Vec<u8> {
+    match kind {
+        Kind::Original | Kind::Mini | Kind::MiniMk2 => {
+            let mut buf = vec![0x0B, 0x63];
+
+            buf.extend(vec![0u8; 15]);
+
+            buf
+        }
+
+        _ => {
+            let mut buf = vec![0x03, 0x02];
+
+            buf.extend(vec![0u8; 30]);
+
+            buf
+        }
+    }
+}
+
+/// Builds feature report that sets brightness of the device, value range is 0 - 100
+pub fn encode_brightness(kind: Kind, percent: u8) -> Vec<u8> {
+    let percent = percent.clamp(0, 100);
+
+    match kind {
+        Kind::Original | Kind::Mini | Kind::MiniMk2 => {
+            let mut buf = vec![0x05, 0x55, 0xaa, 0xd1, 0x01, percent];
+
+            buf.extend(vec![0u8; 11]);
+
+            buf
+        }
+
+        _ => {
+            let mut buf = vec![0x03, 0x08, percent];
+
+            buf.extend(vec![0u8; 29]);
+
+            buf
+        }
+    }
+}
+
+/// Builds feature report that sets touch point's led strip color
+pub fn encode_touchpoint_color(kind: Kind, point: u8, red: u8, green: u8, blue: u8) -> Result<Vec<u8>, StreamDeckError> {
+    if point >= kind.touchpoint_count() {
+        return Err(StreamDeckError::InvalidTouchPointIndex);
+    }
+
+    let mut buf = vec![0x03, 0x06];
+
+    let touchpoint_index: u8 = point + kind.key_count();
+    buf.extend(vec![touchpoint_index]);
+    buf.extend(vec![red, green, blue]);
+
+    Ok(buf)
+}
+
+/// Splits already encoded key image into output reports
+pub fn encode_key_image(kind: Kind, key: u8, image_data: &[u8]) -> Result<Vec<Vec<u8>>, StreamDeckError> {
+    if key >= kind.key_count() {
+        return Err(StreamDeckError::InvalidKeyIndex);
+    }
+
+    let key = if let Kind::Original = kind { flip_key_index(&kind, key) } else { key };
+
+    if !kind.is_visual() {
+        return Err(StreamDeckError::NoScreen);
+    }
+
+    Ok(encode_image_data_reports(
+        image_data,
+        WriteImageParameters::for_key(kind, image_data.len()),
+        |page_number, this_length, last_package| match kind {
+            Kind::Original => vec![0x02, 0x01, (page_number + 1) as u8, 0, if last_package { 1 } else { 0 }, key + 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+
+            Kind::Mini | Kind::MiniMk2 => vec![0x02, 0x01, page_number as u8, 0, if last_package { 1 } else { 0 }, key + 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+
+            _ => vec![
+                0x02,
+                0x07,
+                key,
+                if last_package { 1 } else { 0 },
+                (this_length & 0xff) as u8,
+                (this_length >> 8) as u8,
+                (page_number & 0xff) as u8,
+                (page_number >> 8) as u8,
+            ],
+        },
+    ))
+}
+
+/// Splits image rect into output reports that write it to the lcd strip/screen as region.
+/// Only Stream Deck Plus supports writing LCD regions
+pub fn encode_lcd_image(kind: Kind, x: u16, y: u16, rect: &ImageRect) -> Result<Vec<Vec<u8>>, StreamDeckError> {
+    match kind {
+        Kind::Plus => (),
+        _ => return Err(StreamDeckError::UnsupportedOperation),
+    }
+
+    Ok(encode_image_data_reports(
+        rect.data.as_slice(),
+        WriteImageParameters {
+            image_report_length: 1024,
+            image_report_payload_length: 1024 - 16,
+        },
+        |page_number, this_length, last_package| {
+            vec![
+                0x02,
+                0x0c,
+                (x & 0xff) as u8,
+                (x >> 8) as u8,
+                (y & 0xff) as u8,
+                (y >> 8) as u8,
+                (rect.w & 0xff) as u8,
+                (rect.w >> 8) as u8,
+                (rect.h & 0xff) as u8,
+                (rect.h >> 8) as u8,
+                if last_package { 1 } else { 0 },
+                (page_number & 0xff) as u8,
+                (page_number >> 8) as u8,
+                (this_length & 0xff) as u8,
+                (this_length >> 8) as u8,
+                0,
+            ]
+        },
+    ))
+}
+
+/// Splits already encoded image into output reports that fill the whole lcd strip/screen.
+/// Supported by Stream Deck Plus and Stream Deck Neo
+pub fn encode_lcd_fill(kind: Kind, image_data: &[u8]) -> Result<Vec<Vec<u8>>, StreamDeckError> {
+    match kind {
+        Kind::Neo => Ok(encode_image_data_reports(
+            image_data,
+            WriteImageParameters {
+                image_report_length: 1024,
+                image_report_payload_length: 1024 - 8,
+            },
+            |page_number, this_length, last_package| {
+                vec![
+                    0x02,
+                    0x0b,
+                    0,
+                    if last_package { 1 } else { 0 },
+                    (this_length & 0xff) as u8,
+                    (this_length >> 8) as u8,
+                    (page_number & 0xff) as u8,
+                    (page_number >> 8) as u8,
+                ]
+            },
+        )),
+
+        Kind::Plus => {
+            let (w, h) = kind.lcd_strip_size().unwrap();
+
+            Ok(encode_image_data_reports(
+                image_data,
+                WriteImageParameters {
+                    image_report_length: 1024,
+                    image_report_payload_length: 1024 - 16,
+                },
+                |page_number, this_length, last_package| {
+                    vec![
+                        0x02,
+                        0x0c,
+                        0,
+                        0,
+                        0,
+                        0,
+                        (w & 0xff) as u8,
+                        (w >> 8) as u8,
+                        (h & 0xff) as u8,
+                        (h >> 8) as u8,
+                        if last_package { 1 } else { 0 },
+                        (page_number & 0xff) as u8,
+                        (page_number >> 8) as u8,
+                        (this_length & 0xff) as u8,
+                        (this_length >> 8) as u8,
+                        0,
+                    ]
+                },
+            ))
+        }
+
+        _ => Err(StreamDeckError::UnsupportedOperation),
+    }
+}
+
+fn encode_image_data_reports<F>(image_data: &[u8], parameters: WriteImageParameters, header_fn: F) -> Vec<Vec<u8>>
+where
+    F: Fn(usize, usize, bool) -> Vec<u8>,
+{
+    let image_report_length = parameters.image_report_length;
+    let image_report_payload_length = parameters.image_report_payload_length;
+
+    let mut reports = vec![];
+
+    let mut page_number = 0;
+    let mut bytes_remaining = image_data.len();
+
+    while bytes_remaining > 0 {
+        let this_length = bytes_remaining.min(image_report_payload_length);
+        let bytes_sent = page_number * image_report_payload_length;
+
+        // Selecting header based on device
+        let mut buf: Vec<u8> = header_fn(page_number, this_length, this_length == bytes_remaining);
+
+        buf.extend(&image_data[bytes_sent..bytes_sent + this_length]);
+
+        // Adding padding
+        buf.extend(vec![0u8; image_report_length - buf.len()]);
+
+        reports.push(buf);
+
+        bytes_remaining -= this_length;
+        page_number += 1;
+    }
+
+    reports
+}
+
+#[derive(Clone, Copy)]
+struct WriteImageParameters {
+    pub image_report_length: usize,
+    pub image_report_payload_length: usize,
+}
+
+impl WriteImageParameters {
+    pub fn for_key(kind: Kind, image_data_len: usize) -> Self {
+        let image_report_length = match kind {
+            Kind::Original => 8191,
+            _ => 1024,
+        };
+
+        let image_report_header_length = match kind {
+            Kind::Original | Kind::Mini | Kind::MiniMk2 => 16,
+            _ => 8,
+        };
+
+        let image_report_payload_length = match kind {
+            Kind::Original => (image_data_len / 2).max(1),
+            _ => image_report_length - image_report_header_length,
+        };
+
+        Self {
+            image_report_length,
+            image_report_payload_length,
+        }
+    }
+}
+
+/// Place on the device where image data is being written to
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum ImageTarget {
+    /// Image of the key
+    Key(u8),
+
+    /// Region of the lcd strip/screen
+    LcdRegion {
+        /// X coordinate of the region
+        x: u16,
+        /// Y coordinate of the region
+        y: u16,
+        /// Width of the region
+        w: u16,
+        /// Height of the region
+        h: u16,
+    },
+
+    /// Whole lcd strip/screen
+    LcdFill,
+}
+
+/// Single page of image data that was parsed from output report
+#[derive(Clone, Debug)]
+pub struct ImagePage<'a> {
+    /// Where the image is being written to
+    pub target: ImageTarget,
+
+    /// Index of the page, starting from 0
+    pub page: usize,
+
+    /// If this is the last page of the image
+    pub last: bool,
+
+    /// Image data carried by the page. Devices that don't put payload length into the header
+    /// (Original, Mini, Mini Mk2) will have padding included
+    pub payload: &'a [u8],
+}
+
+/// Parses output report that carries a page of image data, returns None if report isn't an image page
+pub fn decode_image_page(kind: Kind, report: &[u8]) -> Option<ImagePage<'_>> {
+    if report.len() < 2 || report[0] != 0x02 {
+        return None;
+    }
+
+    let u16_at = |index: usize| u16::from_le_bytes([report[index], report[index + 1]]);
+
+    match (kind, report[1]) {
+        (Kind::Original | Kind::Mini | Kind::MiniMk2, 0x01) if report.len() >= 16 => {
+            let key = report[5].checked_sub(1)?;
+
+            Some(ImagePage {
+                target: ImageTarget::Key(if let Kind::Original = kind { flip_key_index(&kind, key) } else { key }),
+                page: if let Kind::Original = kind { (report[2] as usize).checked_sub(1)? } else { report[2] as usize },
+                last: report[4] != 0,
+                payload: &report[16..],
+            })
+        }
+
+        (Kind::Original | Kind::Mini | Kind::MiniMk2, _) => None,
+
+        (_, 0x07) if report.len() >= 8 => Some(ImagePage {
+            target: ImageTarget::Key(report[2]),
+            page: u16_at(6) as usize,
+            last: report[3] != 0,
+            payload: report.get(8..8 + u16_at(4) as usize)?,
+        }),
+
+        (Kind::Neo, 0x0b) if report.len() >= 8 => Some(ImagePage {
+            target: ImageTarget::LcdFill,
+            page: u16_at(6) as usize,
+            last: report[3] != 0,
+            payload: report.get(8..8 + u16_at(4) as usize)?,
+        }),
+
+        (Kind::Plus, 0x0c) if report.len() >= 16 => Some(ImagePage {
+            target: ImageTarget::LcdRegion {
+                x: u16_at(2),
+                y: u16_at(4),
+                w: u16_at(6),
+                h: u16_at(8),
+            },
+            page: u16_at(11) as usize,
+            last: report[10] != 0,
+            payload: report.get(16..16 + u16_at(13) as usize)?,
+        }),
+
+        _ => None,
+    }
+}
+
+/// Puts pages of image data that are written to the device back together into whole images
+pub struct ImageReassembler {
+    kind: Kind,
+    pending: HashMap<ImageTarget, Vec<u8>>,
+}
+
+impl ImageReassembler {
+    /// Creates reassembler for the device kind
+    pub fn new(kind: Kind) -> ImageReassembler {
+        ImageReassembler { kind, pending: HashMap::new() }
+    }
+
+    /// Feeds output report into the reassembler, returns the image once its last page arrives.
+    /// Reports that aren't image pages are ignored
+    pub fn feed(&mut self, report: &[u8]) -> Option<(ImageTarget, Vec<u8>)> {
+        let page = decode_image_page(self.kind, report)?;
+
+        if page.page == 0 {
+            self.pending.insert(page.target, vec![]);
+        }
+
+        let data = self.pending.get_mut(&page.target)?;
+
+        match self.kind {
+            // Original splits BMP image into two halves, the header doesn't tell how much of the page is padding
+            Kind::Original => {
+                let half = bmp_size(if page.page == 0 { page.payload } else { data })? / 2;
+                data.extend(page.payload.get(..half)?);
+            }
+
+            _ => data.extend(page.payload),
+        }
+
+        if !page.last {
+            return None;
+        }
+
+        let mut data = self.pending.remove(&page.target)?;
+
+        if let Kind::Original | Kind::Mini | Kind::MiniMk2 = self.kind {
+            data.truncate(bmp_size(&data)?);
+        }
+
+        Some((page.target, data))
+    }
+}
+
+fn bmp_size(data: &[u8]) -> Option<usize> {
+    match data {
+        [b'B', b'M', a, b, c, d, ..] => Some(u32::from_le_bytes([*a, *b, *c, *d]) as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockDevice;
+    use crate::StreamDeck;
+
+    #[test]
+    fn brightness_is_sent_as_feature_report() {
+        let mini = MockDevice::new(Kind::Mini);
+        StreamDeck::with_transport(Kind::Mini, mini.clone()).set_brightness(42).unwrap();
+        assert_eq!(mini.feature_reports(), vec![[vec![0x05, 0x55, 0xaa, 0xd1, 0x01, 42], vec![0; 11]].concat()]);
+
+        let plus = MockDevice::new(Kind::Plus);
+        StreamDeck::with_transport(Kind::Plus, plus.clone()).set_brightness(150).unwrap();
+        assert_eq!(plus.feature_reports(), vec![[vec![0x03, 0x08, 100], vec![0; 29]].concat()]);
+    }
+
+    #[test]
+    fn button_states_round_trip() {
+        for kind in [Kind::Original, Kind::OriginalV2, Kind::Mini, Kind::MiniMk2, Kind::Mk2, Kind::Xl, Kind::Pedal, Kind::Neo, Kind::Plus] {
+            let count = kind.key_count() as usize + kind.touchpoint_count() as usize;
+            let states = (0..count).map(|index| index % 3 == 1).collect::<Vec<_>>();
+
+            let report = encode_button_states(kind, &states);
+            assert_eq!(report.len(), input_report_length(kind), "{:?}", kind);
+
+            match decode_input(kind, &report).unwrap() {
+                StreamDeckInput::ButtonStateChange(decoded) => assert_eq!(&decoded[..count], &states[..], "{:?}", kind),
+                other => panic!("{:?} decoded into {:?}", kind, other),
+            }
+        }
+    }
+
+    #[test]
+    fn encoder_input_round_trips() {
+        let twist = encode_encoder_twist(Kind::Plus, &[-3, 0, 127, -128]);
+        assert_eq!(&twist[..9], &[0x01, 0x03, 0, 0, 0x01, 0xfd, 0, 0x7f, 0x80]);
+        assert!(matches!(decode_input(Kind::Plus, &twist).unwrap(), StreamDeckInput::EncoderTwist(ticks) if ticks == [-3, 0, 127, -128]));
+
+        let states = encode_encoder_states(Kind::Plus, &[false, true, false, true]);
+        assert!(matches!(decode_input(Kind::Plus, &states).unwrap(), StreamDeckInput::EncoderStateChange(states) if states == [false, true, false, true]));
+    }
+
+    #[test]
+    fn touchscreen_input_round_trips() {
+        let press = encode_touchscreen_press(Kind::Plus, 650, 40);
+        assert!(matches!(decode_input(Kind::Plus, &press).unwrap(), StreamDeckInput::TouchScreenPress(650, 40)));
+
+        let long_press = encode_touchscreen_long_press(Kind::Plus, 10, 99);
+        assert!(matches!(decode_input(Kind::Plus, &long_press).unwrap(), StreamDeckInput::TouchScreenLongPress(10, 99)));
+
+        let swipe = encode_touchscreen_swipe(Kind::Plus, (100, 50), (700, 60));
+        assert!(matches!(decode_input(Kind::Plus, &swipe).unwrap(), StreamDeckInput::TouchScreenSwipe((100, 50), (700, 60))));
+    }
+
+    #[test]
+    fn empty_and_short_reports_are_decoded() {
+        assert!(decode_input(Kind::Mk2, &[0; 32]).unwrap().is_empty());
+        assert!(matches!(decode_input(Kind::Mk2, &[1, 0, 0]), Err(StreamDeckError::BadData)));
+        assert!(matches!(decode_input(Kind::Plus, &[1, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(StreamDeckError::BadData)));
+    }
+
+    #[test]
+    fn key_image_pages_round_trip() {
+        let image = (0..3000).map(|index| index as u8).collect::<Vec<_>>();
+        let reports = encode_key_image(Kind::Xl, 31, &image).unwrap();
+
+        assert_eq!(reports.len(), 3);
+        assert!(reports.iter().all(|report| report.len() == 1024));
+
+        let pages = reports.iter().map(|report| decode_image_page(Kind::Xl, report).unwrap()).collect::<Vec<_>>();
+        assert!(pages.iter().all(|page| page.target == ImageTarget::Key(31)));
+        assert_eq!(pages.iter().map(|page| page.page).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(pages.iter().map(|page| page.last).collect::<Vec<_>>(), [false, false, true]);
+        assert_eq!(pages.iter().flat_map(|page| page.payload.iter().copied()).collect::<Vec<_>>(), image);
+
+        let mut reassembler = ImageReassembler::new(Kind::Xl);
+        let results = reports.iter().filter_map(|report| reassembler.feed(report)).collect::<Vec<_>>();
+        assert_eq!(results, [(ImageTarget::Key(31), image)]);
+    }
+
+    #[test]
+    fn key_images_are_checked() {
+        assert!(matches!(encode_key_image(Kind::Mini, 6, &[0]), Err(StreamDeckError::InvalidKeyIndex)));
+        assert!(matches!(encode_key_image(Kind::Pedal, 0, &[0]), Err(StreamDeckError::NoScreen)));
+        assert!(matches!(encode_lcd_fill(Kind::Mk2, &[0]), Err(StreamDeckError::UnsupportedOperation)));
+    }
+}
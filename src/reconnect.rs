@@ -0,0 +1,436 @@
+//! Stream Deck handle that survives the device being unplugged and plugged back in
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::info::Kind;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let device = Arc::new(StreamDeck::connect_reconnecting(new_hidapi().unwrap(), Kind::Mk2, "AL12K2C02059").unwrap());
+//! device.set_brightness(50).unwrap();
+//!
+//! let reader = device.get_reader();
+//!
+//! loop {
+//!     for update in reader.read(Some(Duration::from_millis(100))).unwrap() {
+//!         if let DeviceStateUpdate::Reconnected = update {
+//!             println!("Device is back, brightness and images were restored");
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use hidapi::{HidApi, HidDevice, HidError, HidResult};
+
+use crate::info::Kind;
+use crate::protocol::{decode_image_page, encode_reset, ImageTarget};
+use crate::transport::Transport;
+use crate::{StreamDeck, StreamDeckError};
+
+/// How often the device is looked for while it's disconnected
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Transport that reopens the device with the same serial number after it was disconnected.
+///
+/// Remembers last brightness, touch point colors, key images and LCD contents that were sent,
+/// and sends them again once the device is reopened. Anything written while the device is disconnected
+/// is remembered the same way and will appear on the device once it's back.
+/// Reads return no data while the device is disconnected.
+///
+/// Works with any [Transport], which is opened again with the function the transport was created with
+pub struct ReconnectingTransport<T: Transport = HidDevice> {
+    kind: Kind,
+    serial: String,
+    open: Box<dyn Fn() -> HidResult<T> + Send + Sync>,
+    device: Mutex<Option<T>>,
+    last_attempt: Mutex<Option<Instant>>,
+    reconnected: AtomicBool,
+    state: Mutex<RestoreState>,
+}
+
+#[derive(Default)]
+struct RestoreState {
+    brightness: Option<Vec<u8>>,
+    touchpoint_colors: BTreeMap<u8, Vec<u8>>,
+    /// Pages of complete images in order they were written
+    images: Vec<(ImageTarget, Vec<Vec<u8>>)>,
+    /// Pages of images that weren't completely written yet
+    pending: HashMap<ImageTarget, Vec<Vec<u8>>>,
+}
+
+/// Static functions of the struct
+impl ReconnectingTransport {
+    /// Opens the device of provided kind with provided serial number
+    pub fn connect(hidapi: HidApi, kind: Kind, serial: &str) -> Result<ReconnectingTransport, StreamDeckError> {
+        let device = hidapi.open_serial(kind.vendor_id(), kind.product_id(), serial)?;
+
+        let hidapi = Mutex::new(hidapi);
+        let owned_serial = serial.to_string();
+
+        Ok(ReconnectingTransport::with_device(kind, serial, device, move || {
+            let mut hidapi = lock(&hidapi);
+            hidapi.refresh_devices()?;
+            hidapi.open_serial(kind.vendor_id(), kind.product_id(), &owned_serial)
+        }))
+    }
+}
+
+/// Static functions of the struct
+impl<T: Transport> ReconnectingTransport<T> {
+    /// Opens the device with provided function, which will also be used to open the device again after it was disconnected
+    pub fn with_opener(kind: Kind, serial: &str, open: impl Fn() -> HidResult<T> + Send + Sync + 'static) -> Result<ReconnectingTransport<T>, StreamDeckError> {
+        let device = open()?;
+        Ok(ReconnectingTransport::with_device(kind, serial, device, open))
+    }
+
+    fn with_device(kind: Kind, serial: &str, device: T, open: impl Fn() -> HidResult<T> + Send + Sync + 'static) -> ReconnectingTransport<T> {
+        ReconnectingTransport {
+            kind,
+            serial: serial.to_string(),
+            open: Box::new(open),
+            device: Mutex::new(Some(device)),
+            last_attempt: Mutex::new(None),
+            reconnected: AtomicBool::new(false),
+            state: Mutex::new(RestoreState::default()),
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport> ReconnectingTransport<T> {
+    /// Returns serial number of the device this transport is bound to
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Checks if the device is currently connected
+    pub fn is_connected(&self) -> bool {
+        lock(&self.device).is_some()
+    }
+
+    /// Runs the operation on the device, reopening it first if needed.
+    /// Forgets the device if the operation failed because the device is gone
+    fn run<R>(&self, operation: impl FnOnce(&T) -> HidResult<R>) -> HidResult<R> {
+        let mut device = lock(&self.device);
+
+        if device.is_none() {
+            *device = self.reopen();
+        }
+
+        let Some(connected) = device.as_ref() else {
+            return Err(disconnected());
+        };
+
+        let result = operation(connected);
+
+        if result.is_err() && !self.is_responding(connected) {
+            *device = None;
+        }
+
+        result
+    }
+
+    /// Checks if the handle still works by requesting serial number from the device
+    fn is_responding(&self, device: &T) -> bool {
+        let mut buf = [0u8; 32];
+
+        buf[0] = match self.kind {
+            Kind::Original | Kind::Mini | Kind::MiniMk2 => 0x03,
+            _ => 0x06,
+        };
+
+        device.get_feature_report(&mut buf).is_ok()
+    }
+
+    /// Attempts to open the device again and restore its state, doesn't try more often than [RECONNECT_INTERVAL]
+    fn reopen(&self) -> Option<T> {
+        {
+            let mut last_attempt = lock(&self.last_attempt);
+
+            if last_attempt.is_some_and(|last| last.elapsed() < RECONNECT_INTERVAL) {
+                return None;
+            }
+
+            *last_attempt = Some(Instant::now());
+        }
+
+        let device = (self.open)().ok()?;
+
+        self.restore(&device).ok()?;
+        self.reconnected.store(true, Ordering::Release);
+
+        Some(device)
+    }
+
+    /// Sends everything that was remembered to the reopened device
+    fn restore(&self, device: &T) -> HidResult<()> {
+        let state = lock(&self.state);
+
+        if let Some(brightness) = &state.brightness {
+            device.send_feature_report(brightness)?;
+        }
+
+        for color in state.touchpoint_colors.values() {
+            device.send_feature_report(color)?;
+        }
+
+        for (_, pages) in &state.images {
+            for page in pages {
+                device.write(page)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remember_feature_report(&self, payload: &[u8]) {
+        let mut state = lock(&self.state);
+
+        if payload == encode_reset(self.kind).as_slice() {
+            state.images.clear();
+            state.pending.clear();
+            return;
+        }
+
+        match (self.kind, payload) {
+            (Kind::Original | Kind::Mini | Kind::MiniMk2, [0x05, 0x55, 0xaa, 0xd1, 0x01, ..]) => state.brightness = Some(payload.to_vec()),
+            (Kind::Original | Kind::Mini | Kind::MiniMk2, _) => {}
+            (_, [0x03, 0x08, ..]) => state.brightness = Some(payload.to_vec()),
+            (_, [0x03, 0x06, index, ..]) => {
+                state.touchpoint_colors.insert(*index, payload.to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    fn remember_write(&self, payload: &[u8]) {
+        let Some(page) = decode_image_page(self.kind, payload) else {
+            return;
+        };
+
+        let mut state = lock(&self.state);
+
+        if page.page == 0 {
+            state.pending.insert(page.target, vec![]);
+        }
+
+        let Some(pages) = state.pending.get_mut(&page.target) else {
+            return;
+        };
+
+        pages.push(payload.to_vec());
+
+        if !page.last {
+            return;
+        }
+
+        let pages = state.pending.remove(&page.target).unwrap_or_default();
+
+        // Dropping images that the new one fully covers
+        state.images.retain(|(target, _)| !covers(&page.target, target));
+        state.images.push((page.target, pages));
+    }
+}
+
+impl<T: Transport> Transport for ReconnectingTransport<T> {
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        self.run(|device| device.get_feature_report(buf))
+    }
+
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()> {
+        self.remember_feature_report(payload);
+
+        match self.run(|device| device.send_feature_report(payload)) {
+            Err(_) if !self.is_connected() => Ok(()),
+            result => result,
+        }
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        match self.run(|device| device.read(buf, timeout)) {
+            Err(_) if !self.is_connected() => {
+                if let Some(timeout) = timeout {
+                    sleep(timeout.min(RECONNECT_INTERVAL));
+                }
+
+                Ok(0)
+            }
+
+            result => result,
+        }
+    }
+
+    fn write(&self, payload: &[u8]) -> HidResult<usize> {
+        self.remember_write(payload);
+
+        match self.run(|device| device.write(payload)) {
+            Err(_) if !self.is_connected() => Ok(payload.len()),
+            result => result,
+        }
+    }
+
+    fn manufacturer_string(&self) -> HidResult<Option<String>> {
+        self.run(|device| device.manufacturer_string())
+    }
+
+    fn product_string(&self) -> HidResult<Option<String>> {
+        self.run(|device| device.product_string())
+    }
+
+    fn take_reconnected(&self) -> bool {
+        self.reconnected.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Static functions of the struct
+impl StreamDeck<ReconnectingTransport> {
+    /// Attempts to connect to the device, returned handle will reopen the device by itself
+    /// if it gets unplugged and plugged back in
+    pub fn connect_reconnecting(hidapi: HidApi, kind: Kind, serial: &str) -> Result<StreamDeck<ReconnectingTransport>, StreamDeckError> {
+        Ok(StreamDeck::with_transport(kind, ReconnectingTransport::connect(hidapi, kind, serial)?))
+    }
+}
+
+/// Checks if writing the new image makes the old one invisible
+fn covers(new: &ImageTarget, old: &ImageTarget) -> bool {
+    match (new, old) {
+        (ImageTarget::LcdFill, ImageTarget::LcdFill | ImageTarget::LcdRegion { .. }) => true,
+        (ImageTarget::LcdRegion { x, y, w, h }, ImageTarget::LcdRegion { x: ox, y: oy, w: ow, h: oh }) => {
+            ox >= x && oy >= y && (*ox as u32 + *ow as u32) <= (*x as u32 + *w as u32) && (*oy as u32 + *oh as u32) <= (*y as u32 + *h as u32)
+        }
+        _ => new == old,
+    }
+}
+
+fn disconnected() -> HidError {
+    HidError::HidApiError {
+        message: "Device is disconnected".to_string(),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockDevice;
+    use crate::StreamDeckInput;
+
+    /// Device whose transport opens the mock again after it's connected back
+    fn reconnecting_deck(kind: Kind) -> (MockDevice, StreamDeck<ReconnectingTransport<MockDevice>>) {
+        let mock = MockDevice::new(kind);
+        let opened = mock.clone();
+        let transport = ReconnectingTransport::with_opener(kind, "MOCK00000000", move || Ok(opened.clone())).unwrap();
+        (mock, StreamDeck::with_transport(kind, transport))
+    }
+
+    /// Unplugs the mock and makes the transport notice it
+    fn unplug(mock: &MockDevice, device: &StreamDeck<ReconnectingTransport<MockDevice>>) {
+        mock.set_disconnected(true);
+        assert!(device.read_input(None).unwrap().is_empty());
+    }
+
+    /// Plugs the mock back in and waits until the transport reopens it
+    fn plug(mock: &MockDevice, device: &StreamDeck<ReconnectingTransport<MockDevice>>) {
+        mock.set_disconnected(false);
+        mock.clear_reports();
+        sleep(RECONNECT_INTERVAL);
+        assert!(matches!(device.read_input(None).unwrap(), StreamDeckInput::Reconnected));
+    }
+
+    #[test]
+    fn latest_state_is_restored_after_reconnect() {
+        let (mock, device) = reconnecting_deck(Kind::Mk2);
+
+        device.set_brightness(50).unwrap();
+        device.write_image(0, &[1; 100]).unwrap();
+        device.flush().unwrap();
+
+        unplug(&mock, &device);
+
+        // Written while disconnected, remembered all the same
+        device.set_brightness(30).unwrap();
+        device.write_image(0, &[3; 100]).unwrap();
+        device.write_image(1, &[2; 100]).unwrap();
+        device.flush().unwrap();
+
+        plug(&mock, &device);
+
+        assert_eq!(mock.feature_reports().len(), 1);
+        assert_eq!(mock.feature_reports()[0][..3], [0x03, 0x08, 30]);
+        assert_eq!(mock.output_reports().len(), 2);
+        assert_eq!(mock.key_image(0), Some(vec![3; 100]));
+        assert_eq!(mock.key_image(1), Some(vec![2; 100]));
+    }
+
+    #[test]
+    fn latest_touch_point_colors_are_restored() {
+        let (mock, device) = reconnecting_deck(Kind::Neo);
+
+        device.set_touchpoint_color(0, 255, 0, 0).unwrap();
+        device.set_touchpoint_color(1, 0, 255, 0).unwrap();
+        device.set_touchpoint_color(0, 0, 0, 255).unwrap();
+
+        unplug(&mock, &device);
+        plug(&mock, &device);
+
+        let reports = mock.feature_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0][..6], [0x03, 0x06, 8, 0, 0, 255]);
+        assert_eq!(reports[1][..6], [0x03, 0x06, 9, 0, 255, 0]);
+    }
+
+    #[test]
+    fn images_are_forgotten_after_reset() {
+        let (mock, device) = reconnecting_deck(Kind::Mk2);
+
+        device.write_image(0, &[1; 100]).unwrap();
+        device.flush().unwrap();
+        device.reset().unwrap();
+
+        unplug(&mock, &device);
+        plug(&mock, &device);
+
+        assert!(mock.feature_reports().is_empty());
+        assert!(mock.output_reports().is_empty());
+    }
+
+    #[test]
+    fn images_that_werent_completely_written_arent_restored() {
+        let (mock, device) = reconnecting_deck(Kind::Mk2);
+        let transport = device.transport().unwrap();
+
+        // First page of a two page image
+        let pages = crate::protocol::encode_key_image(Kind::Mk2, 0, &[1; 2000]).unwrap();
+        assert_eq!(pages.len(), 2);
+        transport.write(&pages[0]).unwrap();
+        drop(transport);
+
+        unplug(&mock, &device);
+        plug(&mock, &device);
+
+        assert!(mock.output_reports().is_empty());
+    }
+
+    #[test]
+    fn new_images_cover_old_ones() {
+        let region = |x, y, w, h| ImageTarget::LcdRegion { x, y, w, h };
+
+        assert!(covers(&ImageTarget::Key(1), &ImageTarget::Key(1)));
+        assert!(!covers(&ImageTarget::Key(1), &ImageTarget::Key(2)));
+
+        assert!(covers(&ImageTarget::LcdFill, &region(0, 0, 100, 100)));
+        assert!(!covers(&region(0, 0, 100, 100), &ImageTarget::LcdFill));
+
+        assert!(covers(&region(0, 0, 200, 100), &region(50, 0, 100, 100)));
+        assert!(!covers(&region(0, 0, 200, 100), &region(150, 0, 100, 100)));
+    }
+}
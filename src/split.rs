@@ -0,0 +1,262 @@
+//! Input and output halves of a Stream Deck that can be used from different threads
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, DeviceStateUpdate, StreamDeck};
+//! use elgato_streamdeck::info::Kind;
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = StreamDeck::connect(&hidapi, Kind::Xl, "CL12K2C02059").unwrap();
+//! let (mut input, output) = device.split();
+//!
+//! thread::spawn(move || loop {
+//!     for key in 0..output.kind().key_count() {
+//!         output.clear_button_image(key).unwrap();
+//!         output.flush().unwrap();
+//!     }
+//! });
+//!
+//! loop {
+//!     for update in input.read(Some(Duration::from_secs(60))).unwrap() {
+//!         if let DeviceStateUpdate::ButtonDown(key) = update {
+//!             println!("Key {} pressed", key);
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hidapi::HidDevice;
+use image::DynamicImage;
+
+use crate::chords::ChordDetector;
+use crate::encoders::EncoderTracker;
+use crate::gestures::GestureRecognizer;
+use crate::images::{ConversionOptions, ImageRect};
+use crate::info::Kind;
+use crate::transport::Transport;
+use crate::{DeviceStateReader, DeviceStateUpdate, StreamDeck, StreamDeckError, StreamDeckInput, TimedUpdate};
+
+/// Input half of a Stream Deck, owns state of buttons and encoders and turns input into updates.
+///
+/// Waits for input in short slices, so the [output half](OutputHalf) never waits long for a read to finish
+pub struct InputHalf<T: Transport = HidDevice> {
+    reader: Arc<DeviceStateReader<T>>,
+}
+
+/// Output half of a Stream Deck, writes images, brightness, LCD and touch point colors.
+/// Can be cloned to write from several threads
+pub struct OutputHalf<T: Transport = HidDevice> {
+    device: Arc<StreamDeck<T>>,
+}
+
+impl<T: Transport> Clone for OutputHalf<T> {
+    fn clone(&self) -> Self {
+        OutputHalf { device: self.device.clone() }
+    }
+}
+
+/// Static functions of the struct
+impl<T: Transport> InputHalf<T> {
+    pub(crate) fn new(device: Arc<StreamDeck<T>>) -> InputHalf<T> {
+        InputHalf { reader: device.get_reader() }
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport> InputHalf<T> {
+    /// Returns kind of the Stream Deck
+    pub fn kind(&self) -> Kind {
+        self.reader.device.kind
+    }
+
+    /// Reads all possible input from Stream Deck device, waits for it up to timeout if it's specified,
+    /// returns immediately otherwise
+    pub fn read_input(&self, timeout: Option<Duration>) -> Result<StreamDeckInput, StreamDeckError> {
+        Ok(self.read_input_timed(timeout)?.0)
+    }
+
+    /// Reads all possible input from Stream Deck device, together with the time the report was read at
+    pub fn read_input_timed(&self, timeout: Option<Duration>) -> Result<(StreamDeckInput, Instant), StreamDeckError> {
+        self.reader.device.read_input_timed(timeout)
+    }
+
+    /// Reads states and returns updates
+    pub fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<DeviceStateUpdate>, StreamDeckError> {
+        Ok(self.read_timed(timeout)?.into_iter().map(|timed| timed.update).collect())
+    }
+
+    /// Reads states and returns updates together with time they happened at and how long things were held for
+    pub fn read_timed(&mut self, timeout: Option<Duration>) -> Result<Vec<TimedUpdate>, StreamDeckError> {
+        self.reader.read_timed(timeout)
+    }
+
+    /// Enables gesture recognition with provided recognizer, or disables it if None
+    pub fn set_gestures(&mut self, gestures: Option<GestureRecognizer>) -> Result<(), StreamDeckError> {
+        self.reader.set_gestures(gestures)
+    }
+
+    /// Enables chord detection with provided detector, or disables it if None
+    pub fn set_chords(&mut self, chords: Option<ChordDetector>) -> Result<(), StreamDeckError> {
+        self.reader.set_chords(chords)
+    }
+
+    /// Enables encoder value tracking with provided tracker, or disables it if None
+    pub fn set_encoders(&mut self, encoders: Option<EncoderTracker>) -> Result<(), StreamDeckError> {
+        self.reader.set_encoders(encoders)
+    }
+
+    /// Enables or disables updates about encoder segments of the touch screen being tapped, long pressed and swiped
+    pub fn set_segments(&mut self, enabled: bool) -> Result<(), StreamDeckError> {
+        self.reader.set_segments(enabled)
+    }
+}
+
+/// Static functions of the struct
+impl<T: Transport> OutputHalf<T> {
+    pub(crate) fn new(device: Arc<StreamDeck<T>>) -> OutputHalf<T> {
+        OutputHalf { device }
+    }
+}
+
+/// Instance methods of the struct
+impl<T: Transport> OutputHalf<T> {
+    /// Returns kind of the Stream Deck
+    pub fn kind(&self) -> Kind {
+        self.device.kind
+    }
+
+    /// Returns manufacturer string of the device
+    pub fn manufacturer(&self) -> Result<String, StreamDeckError> {
+        self.device.manufacturer()
+    }
+
+    /// Returns product string of the device
+    pub fn product(&self) -> Result<String, StreamDeckError> {
+        self.device.product()
+    }
+
+    /// Returns serial number of the device
+    pub fn serial_number(&self) -> Result<String, StreamDeckError> {
+        self.device.serial_number()
+    }
+
+    /// Returns firmware version of the StreamDeck
+    pub fn firmware_version(&self) -> Result<String, StreamDeckError> {
+        self.device.firmware_version()
+    }
+
+    /// Resets the device
+    pub fn reset(&self) -> Result<(), StreamDeckError> {
+        self.device.reset()
+    }
+
+    /// Sets brightness of the device, value range is 0 - 100
+    pub fn set_brightness(&self, percent: u8) -> Result<(), StreamDeckError> {
+        self.device.set_brightness(percent)
+    }
+
+    /// Writes image data to Stream Deck device, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), StreamDeckError> {
+        self.device.write_image(key, image_data)
+    }
+
+    /// Writes image data to Stream Deck device's lcd strip/screen as region.
+    /// Only Stream Deck Plus supports writing LCD regions, for Stream Deck Neo use write_lcd_fill
+    pub fn write_lcd(&self, x: u16, y: u16, rect: &ImageRect) -> Result<(), StreamDeckError> {
+        self.device.write_lcd(x, y, rect)
+    }
+
+    /// Writes image data to Stream Deck device's lcd strip/screen as full fill
+    pub fn write_lcd_fill(&self, image_data: &[u8]) -> Result<(), StreamDeckError> {
+        self.device.write_lcd_fill(image_data)
+    }
+
+    /// Sets button's image to blank, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn clear_button_image(&self, key: u8) -> Result<(), StreamDeckError> {
+        self.device.clear_button_image(key)
+    }
+
+    /// Sets blank images to every button, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn clear_all_button_images(&self) -> Result<(), StreamDeckError> {
+        self.device.clear_all_button_images()
+    }
+
+    /// Sets specified button's image, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), StreamDeckError> {
+        self.device.set_button_image(key, image)
+    }
+
+    /// Sets images of several buttons at once, images are converted in parallel.
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub fn set_button_images(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>) -> Result<(), StreamDeckError> {
+        self.device.set_button_images(images)
+    }
+
+    /// Sets specified button's image converted with provided options, changes must be flushed with `.flush()` before
+    /// they will appear on the device!
+    pub fn set_button_image_with_options(&self, key: u8, image: DynamicImage, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        self.device.set_button_image_with_options(key, image, options)
+    }
+
+    /// Sets images of several buttons at once converted in parallel with provided options.
+    /// Changes must be flushed with `.flush()` before they will appear on the device!
+    pub fn set_button_images_with_options(&self, images: impl IntoIterator<Item = (u8, DynamicImage)>, options: &ConversionOptions) -> Result<(), StreamDeckError> {
+        self.device.set_button_images_with_options(images, options)
+    }
+
+    /// Sets specified touch point's led strip color
+    pub fn set_touchpoint_color(&self, point: u8, red: u8, green: u8, blue: u8) -> Result<(), StreamDeckError> {
+        self.device.set_touchpoint_color(point, red, green, blue)
+    }
+
+    /// Flushes the button's image to the device, sending at most one image per key and skipping keys that already show the image.
+    /// Returns keys that were uploaded
+    pub fn flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.device.flush()
+    }
+
+    /// Flushes the button's image to the device, uploading every image even if the key already shows it
+    pub fn force_flush(&self) -> Result<Vec<u8>, StreamDeckError> {
+        self.device.force_flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::info::Kind;
+    use crate::mock::mock_deck;
+    use crate::DeviceStateUpdate;
+
+    #[test]
+    fn output_half_writes_while_input_half_waits_for_input() {
+        let (mock, device) = mock_deck(Kind::Mk2);
+        let (mut input, output) = device.split();
+
+        let reader = thread::spawn(move || input.read(Some(Duration::from_secs(10))).unwrap());
+        thread::sleep(Duration::from_millis(20));
+
+        for key in 0..output.kind().key_count() {
+            output.write_image(key, &[key; 100]).unwrap();
+            assert_eq!(output.flush().unwrap(), [key]);
+        }
+
+        assert!(!reader.is_finished());
+        mock.queue_button_states(&[true]);
+
+        assert!(matches!(reader.join().unwrap()[..], [DeviceStateUpdate::ButtonDown(0)]));
+        for key in 0..output.kind().key_count() {
+            assert_eq!(mock.key_image(key), Some(vec![key; 100]));
+        }
+    }
+}
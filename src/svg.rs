@@ -0,0 +1,138 @@
+//! Rasterizing SVG icons at the resolution of keys and LCD
+//!
+//! Icons are rendered straight at the target size instead of being scaled from a bitmap, so they stay sharp.
+//! Rendered images keep transparency and go through the usual conversion, including its [background](crate::images::ConversionOptions::background)
+//!
+//! Only shapes, paths, gradients and nested SVG images are drawn. `<text>` elements and embedded PNG, JPEG, GIF or WebP images
+//! are skipped, since the renderer is built without font and raster decoding support.
+//! Convert text to paths and embed raster images as SVG beforehand, or draw text with the `fonts` feature instead
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, StreamDeck};
+//! use elgato_streamdeck::info::Kind;
+//! use elgato_streamdeck::svg::{render_key_svg, SvgImage};
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = StreamDeck::connect(&hidapi, Kind::Mk2, "AL12K2C02059").unwrap();
+//! let icon = SvgImage::open("icons/microphone.svg").unwrap();
+//!
+//! device.set_button_image(0, render_key_svg(device.kind(), &icon)).unwrap();
+//! device.flush().unwrap();
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+
+use crate::images::ImageRect;
+use crate::info::Kind;
+use crate::StreamDeckError;
+
+/// Parsed SVG image, can be rendered at any size
+pub struct SvgImage {
+    tree: Tree,
+}
+
+/// Static functions of the struct
+impl SvgImage {
+    /// Parses SVG from its data, gzip compressed SVG is also accepted
+    pub fn from_bytes(data: &[u8]) -> io::Result<SvgImage> {
+        let tree = Tree::from_data(data, &Options::default()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(SvgImage { tree })
+    }
+
+    /// Parses SVG file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<SvgImage> {
+        SvgImage::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Instance methods of the struct
+impl SvgImage {
+    /// Returns size of the SVG as it's declared in the document
+    pub fn size(&self) -> (f32, f32) {
+        let size = self.tree.size();
+        (size.width(), size.height())
+    }
+
+    /// Renders SVG into image of provided size. SVG is scaled to fit keeping aspect ratio and is put in the middle,
+    /// rest of the image is transparent. Text and raster images of the SVG aren't drawn, see [module docs](crate::svg)
+    pub fn render(&self, width: u32, height: u32) -> DynamicImage {
+        let Some(mut pixmap) = Pixmap::new(width, height) else {
+            return DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+        };
+
+        let (svg_width, svg_height) = self.size();
+        let scale = (width as f32 / svg_width).min(height as f32 / svg_height);
+
+        let transform = Transform::from_scale(scale, scale).post_translate((width as f32 - svg_width * scale) / 2.0, (height as f32 - svg_height * scale) / 2.0);
+
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // Pixmap has premultiplied alpha
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                Rgba([color.red(), color.green(), color.blue(), color.alpha()]).0
+            })
+            .collect();
+
+        DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).expect("pixmap has size of the image"))
+    }
+}
+
+/// Renders SVG into image of the key size of provided device kind, without text and raster images like [render](SvgImage::render)
+pub fn render_key_svg(kind: Kind, svg: &SvgImage) -> DynamicImage {
+    let (width, height) = kind.key_image_format().size;
+    svg.render(width as u32, height as u32)
+}
+
+/// Renders SVG into image rect of provided size, to be written to LCD with [write_lcd](crate::StreamDeck::write_lcd).
+/// Text and raster images aren't drawn like with [render](SvgImage::render)
+pub fn render_svg_rect(svg: &SvgImage, width: u16, height: u16) -> Result<ImageRect, StreamDeckError> {
+    ImageRect::from_image(svg.render(width as u32, height as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImageView;
+
+    use super::*;
+
+    const WIDE_RECT: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="20" height="10" fill="red"/></svg>"#;
+
+    #[test]
+    fn svg_is_scaled_into_the_middle() {
+        let svg = SvgImage::from_bytes(WIDE_RECT.as_bytes()).unwrap();
+        assert_eq!(svg.size(), (20.0, 10.0));
+
+        let image = svg.render(40, 40);
+        assert_eq!(image.dimensions(), (40, 40));
+
+        // SVG is scaled to 40x20, area above and below it stays transparent
+        for (x, y, pixel) in image.pixels() {
+            let expected = if (10..30).contains(&y) { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) };
+            assert_eq!(pixel, expected, "pixel {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn key_svg_has_key_size() {
+        let svg = SvgImage::from_bytes(WIDE_RECT.as_bytes()).unwrap();
+
+        assert_eq!(render_key_svg(Kind::Mk2, &svg).dimensions(), (72, 72));
+        assert_eq!(render_key_svg(Kind::Mini, &svg).dimensions(), (80, 80));
+    }
+
+    #[test]
+    fn invalid_svg_is_rejected() {
+        let err = SvgImage::from_bytes(b"<svg").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,422 @@
+//! Rendering text into images for keys and LCD
+//!
+//! Rendered images are regular [DynamicImage]s that can be passed to [set_button_image](crate::StreamDeck::set_button_image)
+//! or anything else that takes images. Areas without text are transparent unless [background](crate::text::TextStyle::background) is set,
+//! so they take background of [conversion options](crate::images::ConversionOptions)
+//!
+//! ```no_run
+//! use elgato_streamdeck::{new_hidapi, StreamDeck};
+//! use elgato_streamdeck::info::Kind;
+//! use elgato_streamdeck::text::{render_key_text, Font, TextStyle};
+//!
+//! let hidapi = new_hidapi().unwrap();
+//! let device = StreamDeck::connect(&hidapi, Kind::Mk2, "AL12K2C02059").unwrap();
+//! let font = Font::open("/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf").unwrap();
+//!
+//! let label = render_key_text(device.kind(), &font, "Mute microphone", &TextStyle::default());
+//! device.set_button_image(0, label).unwrap();
+//! device.flush().unwrap();
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ab_glyph::{point, Font as _, FontArc, PxScale, ScaleFont};
+use image::{DynamicImage, Pixel, Rgba, RgbaImage};
+
+use crate::images::ImageRect;
+use crate::info::Kind;
+use crate::StreamDeckError;
+
+/// TrueType or OpenType font used to render text
+#[derive(Clone)]
+pub struct Font {
+    font: FontArc,
+}
+
+/// Static functions of the struct
+impl Font {
+    /// Loads font from TTF or OTF data
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Font> {
+        let font = FontArc::try_from_vec(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Font { font })
+    }
+
+    /// Loads font from TTF or OTF file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Font> {
+        Font::from_bytes(fs::read(path)?)
+    }
+}
+
+/// Horizontal alignment of text lines
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum HorizontalAlign {
+    /// Lines start at the left edge
+    Left,
+
+    /// Lines are centered
+    #[default]
+    Center,
+
+    /// Lines end at the right edge
+    Right,
+}
+
+/// Vertical alignment of the whole text
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum VerticalAlign {
+    /// Text starts at the top edge
+    Top,
+
+    /// Text is centered
+    #[default]
+    Middle,
+
+    /// Text ends at the bottom edge
+    Bottom,
+}
+
+/// Describes how text looks and how it's laid out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    /// Font size in pixels
+    pub size: f32,
+
+    /// Color of the text
+    pub color: Rgba<u8>,
+
+    /// Color of the area behind the text
+    pub background: Rgba<u8>,
+
+    /// Horizontal alignment of lines
+    pub horizontal_align: HorizontalAlign,
+
+    /// Vertical alignment of the text
+    pub vertical_align: VerticalAlign,
+
+    /// If lines that are too long should be broken into several lines
+    pub wrap: bool,
+
+    /// If font size should be lowered until the text fits, down to [min_size](TextStyle::min_size)
+    pub shrink_to_fit: bool,
+
+    /// Smallest font size that shrinking can go down to
+    pub min_size: f32,
+
+    /// Space between the text and edges of the image, in pixels
+    pub padding: u32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            size: 16.0,
+            color: Rgba([255, 255, 255, 255]),
+            background: Rgba([0, 0, 0, 0]),
+            horizontal_align: HorizontalAlign::Center,
+            vertical_align: VerticalAlign::Middle,
+            wrap: true,
+            shrink_to_fit: true,
+            min_size: 8.0,
+            padding: 4,
+        }
+    }
+}
+
+/// Renders text into image of provided size
+pub fn render_text(font: &Font, text: &str, width: u32, height: u32, style: &TextStyle) -> DynamicImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, style.background);
+
+    let max_width = width.saturating_sub(style.padding * 2) as f32;
+    let max_height = height.saturating_sub(style.padding * 2) as f32;
+
+    let (size, lines) = fit_text(font, text, max_width, max_height, style);
+
+    let scaled = font.font.as_scaled(PxScale::from(size));
+    let line_height = scaled.height() + scaled.line_gap();
+    let text_height = line_height * lines.len() as f32 - scaled.line_gap();
+
+    let top = style.padding as f32
+        + match style.vertical_align {
+            VerticalAlign::Top => 0.0,
+            VerticalAlign::Middle => (max_height - text_height) / 2.0,
+            VerticalAlign::Bottom => max_height - text_height,
+        };
+
+    for (index, line) in lines.iter().enumerate() {
+        let left = style.padding as f32
+            + match style.horizontal_align {
+                HorizontalAlign::Left => 0.0,
+                HorizontalAlign::Center => (max_width - line_width(font, line, size)) / 2.0,
+                HorizontalAlign::Right => max_width - line_width(font, line, size),
+            };
+
+        let baseline = top + line_height * index as f32 + scaled.ascent();
+        draw_line(&mut canvas, font, line, size, left, baseline, style.color);
+    }
+
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Renders text into image of the key size of provided device kind
+pub fn render_key_text(kind: Kind, font: &Font, text: &str, style: &TextStyle) -> DynamicImage {
+    let (width, height) = kind.key_image_format().size;
+    render_text(font, text, width as u32, height as u32, style)
+}
+
+/// Renders text into image rect of provided size, to be written to LCD with [write_lcd](crate::StreamDeck::write_lcd)
+pub fn render_text_rect(font: &Font, text: &str, width: u16, height: u16, style: &TextStyle) -> Result<ImageRect, StreamDeckError> {
+    ImageRect::from_image(render_text(font, text, width as u32, height as u32, style))
+}
+
+/// Splits text into lines, breaking lines that are too long if wrapping is enabled.
+/// Also returns if some word had to be broken because it didn't fit on a line by itself
+fn layout(font: &Font, text: &str, size: f32, max_width: f32, wrap: bool) -> (Vec<String>, bool) {
+    let mut lines = vec![];
+    let mut broke_words = false;
+
+    for paragraph in text.lines() {
+        if !wrap {
+            lines.push(paragraph.to_string());
+            continue;
+        }
+
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+
+            if line_width(font, &candidate, size) <= max_width {
+                line = candidate;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            // Breaking words that don't fit on a line by themselves
+            for character in word.chars() {
+                line.push(character);
+
+                if line.chars().count() > 1 && line_width(font, &line, size) > max_width {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, character.to_string()));
+                    broke_words = true;
+                }
+            }
+        }
+
+        lines.push(line);
+    }
+
+    (lines, broke_words)
+}
+
+/// Picks font size for the text according to the style, lowering it if text should shrink to fit.
+/// Returns the size and the lines laid out with it
+fn fit_text(font: &Font, text: &str, max_width: f32, max_height: f32, style: &TextStyle) -> (f32, Vec<String>) {
+    let mut size = style.size;
+    let (mut lines, mut broke_words) = layout(font, text, size, max_width, style.wrap);
+
+    // Words broken in the middle don't count as fitting, unless there's no smaller size to try
+    while style.shrink_to_fit && size > style.min_size && (broke_words || !fits(font, &lines, size, max_width, max_height)) {
+        size = (size - 1.0).max(style.min_size);
+        (lines, broke_words) = layout(font, text, size, max_width, style.wrap);
+    }
+
+    (size, lines)
+}
+
+fn fits(font: &Font, lines: &[String], size: f32, max_width: f32, max_height: f32) -> bool {
+    let scaled = font.font.as_scaled(PxScale::from(size));
+    let text_height = (scaled.height() + scaled.line_gap()) * lines.len() as f32 - scaled.line_gap();
+
+    text_height <= max_height && lines.iter().all(|line| line_width(font, line, size) <= max_width)
+}
+
+fn line_width(font: &Font, line: &str, size: f32) -> f32 {
+    let scaled = font.font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+
+    for character in line.chars() {
+        let glyph = scaled.glyph_id(character);
+
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, glyph);
+        }
+
+        width += scaled.h_advance(glyph);
+        previous = Some(glyph);
+    }
+
+    width
+}
+
+fn draw_line(canvas: &mut RgbaImage, font: &Font, line: &str, size: f32, left: f32, baseline: f32, color: Rgba<u8>) {
+    let scaled = font.font.as_scaled(PxScale::from(size));
+    let mut x = left;
+    let mut previous = None;
+
+    for character in line.chars() {
+        let id = scaled.glyph_id(character);
+
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+
+        let glyph = id.with_scale_and_position(size, point(x, baseline));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+
+        let Some(outline) = font.font.outline_glyph(glyph) else {
+            continue;
+        };
+
+        let bounds = outline.px_bounds();
+
+        outline.draw(|glyph_x, glyph_y, coverage| {
+            let pixel_x = bounds.min.x as i64 + glyph_x as i64;
+            let pixel_y = bounds.min.y as i64 + glyph_y as i64;
+
+            if pixel_x < 0 || pixel_y < 0 || pixel_x >= canvas.width() as i64 || pixel_y >= canvas.height() as i64 {
+                return;
+            }
+
+            let mut pixel = color;
+            pixel.0[3] = (color.0[3] as f32 * coverage.clamp(0.0, 1.0)).round() as u8;
+
+            canvas.get_pixel_mut(pixel_x as u32, pixel_y as u32).blend(&pixel);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds font without outlines where every glyph is half of the font size wide and line height equals the font size
+    fn test_font() -> Font {
+        fn table(data: &[u16]) -> Vec<u8> {
+            data.iter().flat_map(|value| value.to_be_bytes()).collect()
+        }
+
+        // Glyphs 1 - 95 for printable ASCII, glyph 0 for everything else
+        let glyphs = 96;
+
+        let head = table(&[1, 0, 1, 0, 0, 0, 0x5F0F, 0x3CF5, 0, 1000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 500, 800, 0, 8, 2, 0, 0]);
+        let hhea = table(&[1, 0, 800, (-200i16) as u16, 0, 500, 0, 0, 500, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let maxp = table(&[0, 0x5000, glyphs]);
+        let hmtx = table(&[500].into_iter().chain(std::iter::repeat_n(0, glyphs as usize)).collect::<Vec<_>>());
+        let cmap = [
+            // Windows Unicode subtable
+            table(&[0, 1, 3, 1, 0, 12]),
+            // Format 4 with a segment for printable ASCII and the closing one
+            table(&[4, 32, 0, 4, 4, 1, 0]),
+            table(&[0x7E, 0xFFFF, 0, 0x20, 0xFFFF, (1 - 0x20i16) as u16, 1, 0, 0]),
+        ]
+        .concat();
+
+        let tables = [(b"cmap", cmap), (b"head", head), (b"hhea", hhea), (b"hmtx", hmtx), (b"maxp", maxp)];
+
+        let mut data = table(&[1, 0, tables.len() as u16, 64, 2, 16]);
+        let mut offset = data.len() + tables.len() * 16;
+
+        for (tag, contents) in &tables {
+            data.extend_from_slice(*tag);
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+            offset += contents.len().next_multiple_of(4);
+        }
+
+        for (_, contents) in tables {
+            let padding = contents.len().next_multiple_of(4) - contents.len();
+            data.extend(contents);
+            data.extend(std::iter::repeat_n(0, padding));
+        }
+
+        Font::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn test_font_has_expected_metrics() {
+        let font = test_font();
+
+        assert_eq!(line_width(&font, "abcd", 10.0), 20.0);
+        assert_eq!(font.font.as_scaled(PxScale::from(10.0)).height(), 10.0);
+    }
+
+    #[test]
+    fn lines_are_wrapped_between_words() {
+        let font = test_font();
+
+        // 5 characters fit on a line
+        let (lines, broke_words) = layout(&font, "ab cd ef\ngh", 10.0, 25.0, true);
+
+        assert_eq!(lines, ["ab cd", "ef", "gh"]);
+        assert!(!broke_words);
+
+        let (lines, broke_words) = layout(&font, "ab cd ef\ngh", 10.0, 25.0, false);
+
+        assert_eq!(lines, ["ab cd ef", "gh"]);
+        assert!(!broke_words);
+    }
+
+    #[test]
+    fn words_longer_than_a_line_are_broken() {
+        let font = test_font();
+
+        let (lines, broke_words) = layout(&font, "ab abcdefghijkl", 10.0, 25.0, true);
+
+        assert_eq!(lines, ["ab", "abcde", "fghij", "kl"]);
+        assert!(broke_words);
+    }
+
+    #[test]
+    fn text_shrinks_until_words_arent_broken() {
+        let font = test_font();
+
+        let style = TextStyle {
+            size: 16.0,
+            min_size: 8.0,
+            ..Default::default()
+        };
+
+        // 8 characters fit on a line at size 10
+        let (size, lines) = fit_text(&font, "abcdefgh", 40.0, 100.0, &style);
+
+        assert_eq!(size, 10.0);
+        assert_eq!(lines, ["abcdefgh"]);
+
+        // Shrinking stops at min size, breaking the word after all
+        let (size, lines) = fit_text(&font, "abcdefgh", 40.0, 100.0, &TextStyle { min_size: 12.0, ..style });
+
+        assert_eq!(size, 12.0);
+        assert_eq!(lines, ["abcdef", "gh"]);
+
+        let (size, lines) = fit_text(&font, "abcdefgh", 40.0, 100.0, &TextStyle { shrink_to_fit: false, ..style });
+
+        assert_eq!(size, 16.0);
+        assert_eq!(lines, ["abcde", "fgh"]);
+    }
+
+    #[test]
+    fn text_shrinks_until_lines_fit_in_height() {
+        let font = test_font();
+
+        let style = TextStyle {
+            size: 16.0,
+            min_size: 8.0,
+            ..Default::default()
+        };
+
+        // Text takes 2 lines from size 10, but their height fits only from size 9
+        let (size, lines) = fit_text(&font, "ab cd ef", 30.0, 18.0, &style);
+
+        assert_eq!(size, 9.0);
+        assert_eq!(lines, ["ab cd", "ef"]);
+    }
+}
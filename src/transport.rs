@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use hidapi::{HidDevice, HidResult};
+
+/// Low level I/O used by [StreamDeck](crate::StreamDeck) to talk to the device.
+///
+/// Implemented for [HidDevice], can be implemented for anything else that can exchange HID reports,
+/// for example fake devices in tests
+pub trait Transport {
+    /// Gets a feature report, first byte of the buffer must be set to report ID.
+    /// Returns amount of bytes that were read
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize>;
+
+    /// Sends a feature report, first byte of the payload is report ID
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()>;
+
+    /// Reads an input report into the buffer. Waits for data up to timeout if it's specified,
+    /// returns immediately otherwise. Returns amount of bytes that were read, 0 if there was no data
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize>;
+
+    /// Writes an output report, first byte of the payload is report ID.
+    /// Returns amount of bytes that were written
+    fn write(&self, payload: &[u8]) -> HidResult<usize>;
+
+    /// Returns manufacturer string of the device, if there's any
+    fn manufacturer_string(&self) -> HidResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Returns product string of the device, if there's any
+    fn product_string(&self) -> HidResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Returns true once after the transport had to reopen the device because it was disconnected.
+    /// Transports that can't reconnect always return false
+    fn take_reconnected(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for HidDevice {
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        HidDevice::get_feature_report(self, buf)
+    }
+
+    fn send_feature_report(&self, payload: &[u8]) -> HidResult<()> {
+        HidDevice::send_feature_report(self, payload)
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> HidResult<usize> {
+        self.set_blocking_mode(timeout.is_some())?;
+
+        match timeout {
+            Some(timeout) => self.read_timeout(buf, timeout.as_millis() as i32),
+            None => HidDevice::read(self, buf),
+        }
+    }
+
+    fn write(&self, payload: &[u8]) -> HidResult<usize> {
+        HidDevice::write(self, payload)
+    }
+
+    fn manufacturer_string(&self) -> HidResult<Option<String>> {
+        self.get_manufacturer_string()
+    }
+
+    fn product_string(&self) -> HidResult<Option<String>> {
+        self.get_product_string()
+    }
+}
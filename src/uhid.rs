@@ -0,0 +1,358 @@
+//! Virtual Stream Deck that is created through Linux `/dev/uhid`.
+//!
+//! Kernel exposes the virtual device as a regular hidraw device, so it will be enumerated by hidapi
+//! and can be opened with [list_devices](crate::list_devices) and [StreamDeck::connect](crate::StreamDeck::connect)
+//! like any real device. Requires write access to `/dev/uhid`.
+//!
+//! Stream Deck Original (first revision) can't be emulated, its image reports are bigger than uhid allows
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::info::Kind;
+use crate::mock::MockDevice;
+use crate::protocol::{encode_button_states, encode_encoder_states, encode_encoder_twist, encode_touchscreen_long_press, encode_touchscreen_press, encode_touchscreen_swipe};
+use crate::transport::Transport;
+
+const UHID_DESTROY: u32 = 1;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+const UHID_FEATURE_REPORT: u8 = 0;
+const UHID_OUTPUT_REPORT: u8 = 1;
+
+const UHID_DATA_MAX: usize = 4096;
+const UHID_EVENT_SIZE: usize = 4380;
+
+const BUS_USB: u16 = 0x03;
+const EIO: u16 = 5;
+
+/// Report IDs of feature reports that Stream Decks use
+const FEATURE_REPORT_IDS: [u8; 6] = [0x03, 0x04, 0x05, 0x06, 0x08, 0x0b];
+const FEATURE_REPORT_LENGTH: usize = 32;
+
+/// Stream Deck emulated through uhid, the device exists for as long as this struct is alive.
+///
+/// Everything sent to the device is handled by [MockDevice], which can be accessed with [VirtualStreamDeck::device]
+/// to inspect feature reports and reassembled images
+pub struct VirtualStreamDeck {
+    kind: Kind,
+    model: MockDevice,
+    uhid: Arc<File>,
+    buttons: Mutex<Vec<bool>>,
+    encoders: Mutex<Vec<bool>>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Static functions of the struct
+impl VirtualStreamDeck {
+    /// Creates virtual device of provided kind with provided serial number
+    pub fn create(kind: Kind, serial: &str) -> io::Result<VirtualStreamDeck> {
+        if let Kind::Original = kind {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Stream Deck Original can't be emulated through uhid"));
+        }
+
+        let uhid = Arc::new(OpenOptions::new().read(true).write(true).open("/dev/uhid")?);
+
+        let model = MockDevice::new(kind);
+        model.set_serial_number(serial);
+
+        write_event(&uhid, &create_event(kind, serial))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+
+        let worker = {
+            let uhid = uhid.clone();
+            let model = model.clone();
+            let running = running.clone();
+
+            thread::Builder::new().name("streamdeck-uhid".to_string()).spawn(move || handle_events(&uhid, &model, &running))?
+        };
+
+        Ok(VirtualStreamDeck {
+            kind,
+            model,
+            uhid,
+            buttons: Mutex::new(vec![false; kind.key_count() as usize + kind.touchpoint_count() as usize]),
+            encoders: Mutex::new(vec![false; kind.encoder_count() as usize]),
+            running,
+            worker: Some(worker),
+        })
+    }
+}
+
+/// Instance methods of the struct
+impl VirtualStreamDeck {
+    /// Returns kind of the virtual device
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Returns the model that handles reports sent to the virtual device
+    pub fn device(&self) -> &MockDevice {
+        &self.model
+    }
+
+    /// Returns last image that was uploaded to the key, as it was encoded by the crate
+    pub fn key_image(&self, key: u8) -> Option<Vec<u8>> {
+        self.model.key_image(key)
+    }
+
+    /// Presses the button, touch points are indexed after the keys
+    pub fn press_button(&self, index: u8) -> io::Result<()> {
+        self.set_button(index, true)
+    }
+
+    /// Releases the button, touch points are indexed after the keys
+    pub fn release_button(&self, index: u8) -> io::Result<()> {
+        self.set_button(index, false)
+    }
+
+    /// Sends states of all buttons at once
+    pub fn set_button_states(&self, states: &[bool]) -> io::Result<()> {
+        let mut buttons = self.buttons.lock().unwrap_or_else(|e| e.into_inner());
+
+        for (mine, theirs) in buttons.iter_mut().zip(states) {
+            *mine = *theirs;
+        }
+
+        self.send_input(&encode_button_states(self.kind, &buttons))
+    }
+
+    /// Presses the encoder, only Stream Deck Plus has encoders
+    pub fn press_encoder(&self, index: u8) -> io::Result<()> {
+        self.set_encoder(index, true)
+    }
+
+    /// Releases the encoder, only Stream Deck Plus has encoders
+    pub fn release_encoder(&self, index: u8) -> io::Result<()> {
+        self.set_encoder(index, false)
+    }
+
+    /// Twists the encoder by amount of ticks, only Stream Deck Plus has encoders
+    pub fn twist_encoder(&self, index: u8, ticks: i8) -> io::Result<()> {
+        let mut twist = vec![0i8; self.kind.encoder_count() as usize];
+        *twist.get_mut(index as usize).ok_or_else(invalid_index)? = ticks;
+
+        self.send_input(&encode_encoder_twist(self.kind, &twist))
+    }
+
+    /// Short presses the touch screen, only Stream Deck Plus has touch screen
+    pub fn touchscreen_press(&self, x: u16, y: u16) -> io::Result<()> {
+        self.send_input(&encode_touchscreen_press(self.kind, x, y))
+    }
+
+    /// Long presses the touch screen, only Stream Deck Plus has touch screen
+    pub fn touchscreen_long_press(&self, x: u16, y: u16) -> io::Result<()> {
+        self.send_input(&encode_touchscreen_long_press(self.kind, x, y))
+    }
+
+    /// Swipes across the touch screen, only Stream Deck Plus has touch screen
+    pub fn touchscreen_swipe(&self, start: (u16, u16), end: (u16, u16)) -> io::Result<()> {
+        self.send_input(&encode_touchscreen_swipe(self.kind, start, end))
+    }
+
+    /// Sends raw input report from the device
+    pub fn send_input(&self, report: &[u8]) -> io::Result<()> {
+        if report.len() > UHID_DATA_MAX {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Input report is too big"));
+        }
+
+        let mut event = vec![0u8; 6 + report.len()];
+        event[0..4].copy_from_slice(&UHID_INPUT2.to_ne_bytes());
+        event[4..6].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+        event[6..].copy_from_slice(report);
+
+        write_event(&self.uhid, &event)
+    }
+
+    fn set_button(&self, index: u8, pressed: bool) -> io::Result<()> {
+        let mut buttons = self.buttons.lock().unwrap_or_else(|e| e.into_inner());
+        *buttons.get_mut(index as usize).ok_or_else(invalid_index)? = pressed;
+
+        self.send_input(&encode_button_states(self.kind, &buttons))
+    }
+
+    fn set_encoder(&self, index: u8, pressed: bool) -> io::Result<()> {
+        let mut encoders = self.encoders.lock().unwrap_or_else(|e| e.into_inner());
+        *encoders.get_mut(index as usize).ok_or_else(invalid_index)? = pressed;
+
+        self.send_input(&encode_encoder_states(self.kind, &encoders))
+    }
+}
+
+impl Drop for VirtualStreamDeck {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+
+        let _ = write_event(&self.uhid, &UHID_DESTROY.to_ne_bytes());
+    }
+}
+
+fn handle_events(uhid: &File, model: &MockDevice, running: &AtomicBool) {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+
+    while running.load(Ordering::Relaxed) {
+        let mut poll_fd = libc::pollfd {
+            fd: uhid.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // Waking up every now and then to see if the device is being dropped
+        if unsafe { libc::poll(&mut poll_fd, 1, 100) } <= 0 {
+            continue;
+        }
+
+        let length = match (&*uhid).read(&mut event) {
+            Ok(length) if length >= 4 => length,
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+
+        event[length..].fill(0);
+
+        let reply = match u32::from_ne_bytes([event[0], event[1], event[2], event[3]]) {
+            UHID_OUTPUT => {
+                let size = (u16::from_ne_bytes([event[4100], event[4101]]) as usize).min(UHID_DATA_MAX);
+
+                if event[4102] == UHID_OUTPUT_REPORT {
+                    let _ = model.write(&event[4..4 + size]);
+                }
+
+                None
+            }
+
+            UHID_GET_REPORT => {
+                let mut report = [0u8; FEATURE_REPORT_LENGTH + 1];
+                report[0] = event[8];
+
+                let mut reply = vec![0u8; 12 + report.len()];
+                reply[0..4].copy_from_slice(&UHID_GET_REPORT_REPLY.to_ne_bytes());
+                reply[4..8].copy_from_slice(&event[4..8]);
+
+                match (event[9], model.get_feature_report(&mut report)) {
+                    (UHID_FEATURE_REPORT, Ok(size)) => {
+                        reply[10..12].copy_from_slice(&(size as u16).to_ne_bytes());
+                        reply[12..12 + size].copy_from_slice(&report[..size]);
+                    }
+
+                    _ => reply[8..10].copy_from_slice(&EIO.to_ne_bytes()),
+                }
+
+                Some(reply)
+            }
+
+            UHID_SET_REPORT => {
+                let size = (u16::from_ne_bytes([event[10], event[11]]) as usize).min(UHID_DATA_MAX);
+
+                let mut reply = vec![0u8; 10];
+                reply[0..4].copy_from_slice(&UHID_SET_REPORT_REPLY.to_ne_bytes());
+                reply[4..8].copy_from_slice(&event[4..8]);
+
+                match event[9] {
+                    UHID_FEATURE_REPORT => {
+                        let _ = model.send_feature_report(&event[12..12 + size]);
+                    }
+
+                    _ => reply[8..10].copy_from_slice(&EIO.to_ne_bytes()),
+                }
+
+                Some(reply)
+            }
+
+            _ => None,
+        };
+
+        if let Some(reply) = reply
+            && write_event(uhid, &reply).is_err()
+        {
+            return;
+        }
+    }
+}
+
+fn create_event(kind: Kind, serial: &str) -> Vec<u8> {
+    let descriptor = report_descriptor(kind);
+
+    let mut event = vec![0u8; 280 + descriptor.len()];
+    event[0..4].copy_from_slice(&UHID_CREATE2.to_ne_bytes());
+
+    put_str(&mut event[4..132], &format!("Elgato Stream Deck {:?}", kind));
+    put_str(&mut event[132..196], "streamdeck-uhid");
+    put_str(&mut event[196..260], serial);
+
+    event[260..262].copy_from_slice(&(descriptor.len() as u16).to_ne_bytes());
+    event[262..264].copy_from_slice(&BUS_USB.to_ne_bytes());
+    event[264..268].copy_from_slice(&(kind.vendor_id() as u32).to_ne_bytes());
+    event[268..272].copy_from_slice(&(kind.product_id() as u32).to_ne_bytes());
+    event[280..].copy_from_slice(&descriptor);
+
+    event
+}
+
+/// Vendor defined descriptor with input report 0x01, output report 0x02 and feature reports the devices use
+fn report_descriptor(kind: Kind) -> Vec<u8> {
+    let input_length = crate::protocol::input_report_length(kind) - 1;
+    let output_length = 1024 - 1;
+
+    let mut descriptor = vec![
+        0x06, 0x00, 0xff, // Usage Page (Vendor Defined)
+        0x09, 0x01, // Usage (1)
+        0xa1, 0x01, // Collection (Application)
+        0x15, 0x00, // Logical Minimum (0)
+        0x26, 0xff, 0x00, // Logical Maximum (255)
+        0x75, 0x08, // Report Size (8)
+    ];
+
+    let mut add_report = |report_id: u8, count: usize, main_item: [u8; 2]| {
+        descriptor.extend([0x85, report_id, 0x09, 0x01, 0x96, (count & 0xff) as u8, (count >> 8) as u8]);
+        descriptor.extend(main_item);
+    };
+
+    add_report(0x01, input_length, [0x81, 0x02]);
+    add_report(0x02, output_length, [0x91, 0x02]);
+
+    for report_id in FEATURE_REPORT_IDS {
+        add_report(report_id, FEATURE_REPORT_LENGTH, [0xb1, 0x02]);
+    }
+
+    descriptor.push(0xc0); // End Collection
+
+    descriptor
+}
+
+fn put_str(field: &mut [u8], value: &str) {
+    let bytes = value.as_bytes();
+    let length = bytes.len().min(field.len() - 1);
+    field[..length].copy_from_slice(&bytes[..length]);
+}
+
+fn write_event(uhid: &File, event: &[u8]) -> io::Result<()> {
+    let written = (&*uhid).write(event)?;
+
+    if written != event.len() {
+        return Err(io::Error::new(io::ErrorKind::WriteZero, "Incomplete uhid event was written"));
+    }
+
+    Ok(())
+}
+
+fn invalid_index() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Index is out of range for this device")
+}
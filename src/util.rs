@@ -1,10 +1,11 @@
 use std::str::{from_utf8, Utf8Error};
 use std::time::Duration;
-use hidapi::{HidDevice, HidError};
+use hidapi::HidError;
 use crate::{Kind, StreamDeckError, StreamDeckInput};
+use crate::transport::Transport;
 
-/// Performs get_feature_report on [HidDevice]
-pub fn get_feature_report(device: &HidDevice, report_id: u8, length: usize) -> Result<Vec<u8>, HidError> {
+/// Performs get_feature_report on [Transport]
+pub fn get_feature_report<T: Transport + ?Sized>(device: &T, report_id: u8, length: usize) -> Result<Vec<u8>, HidError> {
     let mut buff = vec![0u8; length];
 
     // Inserting report id byte
@@ -16,27 +17,22 @@ pub fn get_feature_report(device: &HidDevice, report_id: u8, length: usize) -> R
     Ok(buff)
 }
 
-/// Performs send_feature_report on [HidDevice]
-pub fn send_feature_report(device: &HidDevice, payload: &[u8]) -> Result<(), HidError> {
+/// Performs send_feature_report on [Transport]
+pub fn send_feature_report<T: Transport + ?Sized>(device: &T, payload: &[u8]) -> Result<(), HidError> {
     device.send_feature_report(payload)
 }
 
-/// Reads data from [HidDevice]. Blocking mode is used if timeout is specified
-pub fn read_data(device: &HidDevice, length: usize, timeout: Option<Duration>) -> Result<Vec<u8>, HidError> {
-    device.set_blocking_mode(timeout.is_some())?;
-
+/// Reads data from [Transport]. Blocking mode is used if timeout is specified
+pub fn read_data<T: Transport + ?Sized>(device: &T, length: usize, timeout: Option<Duration>) -> Result<Vec<u8>, HidError> {
     let mut buf = vec![0u8; length];
 
-    match timeout {
-        Some(timeout) => device.read_timeout(buf.as_mut_slice(), timeout.as_millis() as i32),
-        None => device.read(buf.as_mut_slice()),
-    }?;
+    device.read(buf.as_mut_slice(), timeout)?;
 
     Ok(buf)
 }
 
-/// Writes data to [HidDevice]
-pub fn write_data(device: &HidDevice, payload: &[u8]) -> Result<usize, HidError> {
+/// Writes data to [Transport]
+pub fn write_data<T: Transport + ?Sized>(device: &T, payload: &[u8]) -> Result<usize, HidError> {
     device.write(payload)
 }
 
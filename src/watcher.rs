@@ -0,0 +1,120 @@
+//! Polls HidApi for Stream Decks and reports ones that appeared or disappeared since the last poll
+//!
+//! ```no_run
+//! use elgato_streamdeck::new_hidapi;
+//! use elgato_streamdeck::watcher::{DeviceEvent, DeviceWatcher};
+//! use std::time::Duration;
+//!
+//! let mut watcher = DeviceWatcher::new(new_hidapi().unwrap(), Duration::from_secs(1));
+//!
+//! for event in &mut watcher {
+//!     match event.unwrap() {
+//!         DeviceEvent::Connected(kind, serial) => println!("{:?} {} connected", kind, serial),
+//!         DeviceEvent::Disconnected(kind, serial) => println!("{:?} {} disconnected", kind, serial),
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashSet, VecDeque};
+use std::thread::sleep;
+use std::time::Duration;
+
+use hidapi::HidApi;
+
+use crate::info::Kind;
+use crate::{list_devices, StreamDeckError};
+
+/// Change in the set of connected devices
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum DeviceEvent {
+    /// Device with kind and serial number was connected
+    Connected(Kind, String),
+    /// Device with kind and serial number was disconnected
+    Disconnected(Kind, String),
+}
+
+/// Keeps set of known devices and produces events whenever it changes.
+///
+/// Devices that are already connected when the watcher is created are reported as connected on the first poll.
+/// Works as a blocking iterator that never ends, waiting for poll rate between polls, failed ones included
+pub struct DeviceWatcher {
+    hidapi: HidApi,
+    poll_rate: Duration,
+    known: HashSet<(Kind, String)>,
+    pending: VecDeque<DeviceEvent>,
+    polled: bool,
+}
+
+/// Static functions of the struct
+impl DeviceWatcher {
+    /// Creates watcher that will poll the HidApi every `poll_rate` while iterated
+    pub fn new(hidapi: HidApi, poll_rate: Duration) -> DeviceWatcher {
+        DeviceWatcher {
+            hidapi,
+            poll_rate,
+            known: HashSet::new(),
+            pending: VecDeque::new(),
+            polled: false,
+        }
+    }
+}
+
+/// Instance methods of the struct
+impl DeviceWatcher {
+    /// Returns devices that were connected as of the last poll
+    pub fn known_devices(&self) -> Vec<(Kind, String)> {
+        self.known.iter().cloned().collect()
+    }
+
+    /// Returns the HidApi used by the watcher, can be used to connect to devices it found
+    pub fn hidapi(&self) -> &HidApi {
+        &self.hidapi
+    }
+
+    /// Refreshes the device list once and returns what changed since the last poll, doesn't block
+    pub fn poll(&mut self) -> Result<Vec<DeviceEvent>, StreamDeckError> {
+        self.hidapi.refresh_devices()?;
+
+        let current = list_devices(&self.hidapi).into_iter().collect::<HashSet<_>>();
+
+        let mut events = vec![];
+
+        for (kind, serial) in self.known.difference(&current) {
+            events.push(DeviceEvent::Disconnected(*kind, serial.clone()));
+        }
+
+        for (kind, serial) in current.difference(&self.known) {
+            events.push(DeviceEvent::Connected(*kind, serial.clone()));
+        }
+
+        self.known = current;
+        self.polled = true;
+
+        Ok(events)
+    }
+}
+
+impl Iterator for DeviceWatcher {
+    type Item = Result<DeviceEvent, StreamDeckError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.polled {
+                sleep(self.poll_rate);
+            }
+
+            match self.poll() {
+                Ok(events) => self.pending.extend(events),
+                Err(err) => {
+                    // Waiting before the next attempt too, so failing polls don't spin
+                    self.polled = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}